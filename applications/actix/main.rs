@@ -1,30 +1,41 @@
 use clap::{App as ClApp, Arg};
-use futures_util::StreamExt;
-use std::sync::Mutex;
+use futures_util::{stream, FutureExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use actix_web::{post, web, App, Error, HttpResponse, HttpServer};
+use actix_web::{http::header, post, web, App, Error, HttpRequest, HttpResponse, HttpServer};
 
-use picolang::rules::RuleFile;
-use picolang::runtime::PicoRuntime;
+use picolang::errors::RuntimeError;
+use picolang::lint::{self, Lint};
+use picolang::rules::loaders::RuleFormat;
+use picolang::rules::schemas::SchemaViolation;
+#[cfg(feature = "archive")]
+use picolang::rules::sources::ArchiveSource;
+#[cfg(feature = "sqlite")]
+use picolang::runtime::SqliteStore;
+use picolang::runtime::{
+  globals_from_env, globals_from_file, PicoRuntime, RuleSetVersion, GLOBAL_ENV_PREFIX,
+};
+use picolang::schema;
+use picolang::PicoValue;
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate serde_derive;
 
-async fn exec_rule<'a>(
+async fn exec_rule(
   rulename: &str,
-  runtime: &PicoRuntime<'a>,
+  version: Option<&str>,
+  runtime: &PicoRuntime,
   mut payload: web::Payload,
 ) -> Result<HttpResponse, Error> {
-  if !runtime.has_rule(&rulename) {
+  if version.is_none() && !runtime.has_rule(rulename) {
     warn!("Rule does not exist {}", rulename);
     return HttpResponse::NotFound().await;
   }
 
-  // extract the full body
-  let mut body = web::BytesMut::new();
-  while let Some(chunk) = payload.next().await {
-    body.extend_from_slice(&chunk?);
-  }
+  let body = read_body(&mut payload).await?;
 
   // then deserialize
   let json_result = serde_json::from_slice::<serde_json::Value>(&body);
@@ -37,8 +48,25 @@ async fn exec_rule<'a>(
 
   let mut ctx = runtime.make_ctx(json);
 
-  match runtime.exec_rule_with_context(&rulename, &mut ctx) {
+  let result = match version {
+    Some(version) => runtime.exec_rule_at_version(version, rulename, &mut ctx),
+    None => runtime.exec_rule_with_context(rulename, &mut ctx),
+  };
+
+  match result {
     Ok(final_ctx) => HttpResponse::Ok().json(final_ctx).await,
+    Err(RuntimeError::InvalidInput { violations, .. }) => {
+      HttpResponse::BadRequest()
+        .json(SchemaResponse { errors: violations })
+        .await
+    }
+    // the rule broke its own contract
+    Err(RuntimeError::InvalidOutput { violations, .. }) => {
+      error!("rule {} output does not match its schema", rulename);
+      HttpResponse::InternalServerError()
+        .json(SchemaResponse { errors: violations })
+        .await
+    }
     Err(x) => {
       error!("rule failed {}", x);
       let s = format!("{}", x);
@@ -48,54 +76,456 @@ async fn exec_rule<'a>(
   }
 }
 
+#[derive(Serialize)]
+struct SchemaResponse {
+  errors: Vec<SchemaViolation>,
+}
+
+// extract the full body
+async fn read_body(payload: &mut web::Payload) -> Result<web::BytesMut, Error> {
+  let mut body = web::BytesMut::new();
+  while let Some(chunk) = payload.next().await {
+    body.extend_from_slice(&chunk?);
+  }
+  Ok(body)
+}
+
+/// batch inputs run in parallel this many at a time, each part is streamed back when it is done
+const BATCH_PART_SIZE: usize = 1024;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchResult {
+  Done(HashMap<String, PicoValue>),
+  Failed { error: String },
+}
+
+type BatchInput = Result<serde_json::Value, String>;
+
+/// Splits a batch body into inputs as its chunks arrive, either a JSON array of inputs
+/// or one input per line (NDJSON), where each input may fail on its own
+#[derive(Default)]
+struct BatchSplitter {
+  // decided by the first byte that is not whitespace
+  is_array: Option<bool>,
+  input: Vec<u8>,
+  // nesting within the current array element
+  depth: usize,
+  in_string: bool,
+  escaped: bool,
+  closed: bool,
+}
+
+impl BatchSplitter {
+  fn push(&mut self, chunk: &[u8], inputs: &mut Vec<BatchInput>) {
+    for &b in chunk {
+      match self.is_array {
+        None if b.is_ascii_whitespace() => {}
+        None => {
+          self.is_array = Some(b == b'[');
+          if b != b'[' {
+            self.input.push(b);
+          }
+        }
+        Some(false) if b == b'\n' => self.emit(inputs),
+        Some(false) => self.input.push(b),
+        Some(true) => self.push_array_byte(b, inputs),
+      }
+    }
+  }
+
+  fn push_array_byte(&mut self, b: u8, inputs: &mut Vec<BatchInput>) {
+    if self.closed {
+      if !b.is_ascii_whitespace() {
+        self.input.push(b);
+      }
+      return;
+    }
+    if self.in_string {
+      match (self.escaped, b) {
+        (true, _) => self.escaped = false,
+        (false, b'\\') => self.escaped = true,
+        (false, b'"') => self.in_string = false,
+        _ => {}
+      }
+      self.input.push(b);
+      return;
+    }
+    match b {
+      b',' if self.depth == 0 => return self.emit(inputs),
+      b']' if self.depth == 0 => {
+        self.closed = true;
+        return self.emit(inputs);
+      }
+      b'"' => self.in_string = true,
+      b'[' | b'{' => self.depth += 1,
+      b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+      _ => {}
+    }
+    self.input.push(b);
+  }
+
+  fn emit(&mut self, inputs: &mut Vec<BatchInput>) {
+    let input = std::mem::take(&mut self.input);
+    if input.iter().all(u8::is_ascii_whitespace) {
+      return;
+    }
+    inputs.push(serde_json::from_slice(&input).map_err(|x| format!("parse failure: {}", x)));
+  }
+
+  /// the body has ended, whatever is left is the last input
+  fn finish(&mut self, inputs: &mut Vec<BatchInput>) {
+    match self.is_array {
+      Some(true) if self.closed && !self.input.is_empty() => {
+        self.input.clear();
+        inputs.push(Err(
+          "parse failure: trailing characters after the array".to_string(),
+        ));
+      }
+      Some(true) if !self.closed => {
+        self.input.clear();
+        inputs.push(Err("parse failure: the array is not closed".to_string()));
+      }
+      _ => self.emit(inputs),
+    }
+  }
+}
+
+/// a batch being read from the request and run, one part at a time
+struct BatchRun {
+  payload: web::Payload,
+  splitter: BatchSplitter,
+  // inputs split from chunks read before the response started
+  pending: Vec<BatchInput>,
+  ended: bool,
+  runtime: web::Data<PicoRuntime>,
+  version: Arc<RuleSetVersion>,
+  rulename: String,
+  written: usize,
+}
+
+impl BatchRun {
+  /// reads until there is a whole part to run, the body ends, or the client is slower than the
+  /// rules and the inputs read so far can run while waiting for more
+  async fn next_part(&mut self) -> Vec<BatchInput> {
+    let mut part = std::mem::take(&mut self.pending);
+    while part.len() < BATCH_PART_SIZE && !self.ended {
+      let chunk = match part.is_empty() {
+        true => self.payload.next().await,
+        false => match self.payload.next().now_or_never() {
+          Some(chunk) => chunk,
+          None => break,
+        },
+      };
+      match chunk {
+        Some(Ok(chunk)) => self.splitter.push(&chunk, &mut part),
+        Some(Err(e)) => {
+          part.push(Err(format!("request body: {}", e)));
+          self.ended = true;
+        }
+        None => {
+          self.splitter.finish(&mut part);
+          self.ended = true;
+        }
+      }
+    }
+    if part.len() > BATCH_PART_SIZE {
+      self.pending = part.split_off(BATCH_PART_SIZE);
+    }
+    part
+  }
+
+  async fn run_part(&mut self, part: Vec<BatchInput>) -> Result<web::Bytes, Error> {
+    let runtime = self.runtime.clone();
+    let version = self.version.clone();
+    let rulename = self.rulename.clone();
+    let results =
+      web::block(move || Ok::<_, ()>(exec_batch_part(&runtime, &version, &rulename, part)))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("batch failed"))?;
+
+    let is_array = self.splitter.is_array == Some(true);
+    let mut bytes = Vec::new();
+    for result in results.iter() {
+      if is_array && self.written > 0 {
+        bytes.push(b',');
+      }
+      serde_json::to_writer(&mut bytes, result)?;
+      if !is_array {
+        bytes.push(b'\n');
+      }
+      self.written += 1;
+    }
+    Ok(web::Bytes::from(bytes))
+  }
+}
+
+fn exec_batch_part(
+  runtime: &PicoRuntime,
+  version: &RuleSetVersion,
+  rulename: &str,
+  part: Vec<BatchInput>,
+) -> Vec<BatchResult> {
+  let mut parse_errors = Vec::with_capacity(part.len());
+  let mut inputs = Vec::with_capacity(part.len());
+  for input in part {
+    match input {
+      Ok(json) => {
+        parse_errors.push(None);
+        inputs.push(json);
+      }
+      Err(error) => parse_errors.push(Some(error)),
+    }
+  }
+
+  let mut results = runtime.exec_batch_in(version, rulename, inputs).into_iter();
+  parse_errors
+    .into_iter()
+    .map(|parse_error| match parse_error {
+      Some(error) => BatchResult::Failed { error },
+      None => match results.next() {
+        Some(Ok(final_ctx)) => BatchResult::Done(final_ctx),
+        Some(Err(x)) => BatchResult::Failed {
+          error: x.to_string(),
+        },
+        None => BatchResult::Failed {
+          error: "no result".to_string(),
+        },
+      },
+    })
+    .collect()
+}
+
+#[post("{rulename}/batch")]
+async fn submit_batch(
+  rulename: web::Path<String>,
+  data_rt: web::Data<PicoRuntime>,
+  payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+  // every part runs against the version that was current when the batch arrived
+  let version = data_rt.current();
+  if !version.rules.has(&rulename) {
+    warn!("Rule does not exist {}", rulename);
+    return HttpResponse::NotFound().await;
+  }
+
+  // the format is known from the first bytes, inputs are read and run as the rest arrive
+  let mut run = BatchRun {
+    payload,
+    splitter: BatchSplitter::default(),
+    pending: Vec::new(),
+    ended: false,
+    runtime: data_rt,
+    version,
+    rulename: rulename.into_inner(),
+    written: 0,
+  };
+  while run.splitter.is_array.is_none() {
+    match run.payload.next().await {
+      Some(chunk) => run.splitter.push(&chunk?, &mut run.pending),
+      None => {
+        run.splitter.finish(&mut run.pending);
+        run.ended = true;
+        break;
+      }
+    }
+  }
+  let is_array = run.splitter.is_array == Some(true);
+  info!("batch for {}", run.rulename);
+
+  let results = stream::unfold(run, |mut run| async move {
+    let part = run.next_part().await;
+    if part.is_empty() {
+      return None;
+    }
+    let bytes = run.run_part(part).await;
+    Some((bytes, run))
+  });
+
+  let (open, close, content_type) = match is_array {
+    true => ("[", "]", "application/json"),
+    false => ("", "", "application/x-ndjson"),
+  };
+  let body = stream::once(async move { Ok(web::Bytes::from_static(open.as_bytes())) })
+    .chain(results)
+    .chain(stream::once(async move {
+      Ok(web::Bytes::from_static(close.as_bytes()))
+    }));
+  Ok(
+    HttpResponse::Ok()
+      .content_type(content_type)
+      .streaming(Box::pin(body)),
+  )
+}
+
 #[post("{rulename}")]
-async fn submit_with_rulename<'a>(
+async fn submit_with_rulename(
   rulename: web::Path<String>,
-  data_rt: web::Data<Mutex<PicoRuntime<'a>>>,
+  data_rt: web::Data<PicoRuntime>,
   payload: web::Payload,
 ) -> Result<HttpResponse, Error> {
-  let runtime = data_rt.lock().unwrap();
-  exec_rule(&rulename, &runtime, payload).await
+  exec_rule(&rulename, None, &data_rt, payload).await
 }
 
-async fn submit_default<'a>(
-  data_rt: web::Data<Mutex<PicoRuntime<'a>>>,
+async fn submit_default(
+  data_rt: web::Data<PicoRuntime>,
   payload: web::Payload,
 ) -> Result<HttpResponse, Error> {
-  let runtime = data_rt.lock().unwrap();
-  exec_rule(&runtime.get_default_rule(), &runtime, payload).await
+  exec_rule(data_rt.get_default_rule(), None, &data_rt, payload).await
 }
 
-async fn rules<'a>(data: web::Data<Mutex<PicoRuntime<'a>>>) -> Result<HttpResponse, Error> {
-  let rt = data.lock().unwrap();
-  let rules = rt.rule_file_names();
+async fn submit_at_version(
+  path: web::Path<(String, String)>,
+  data_rt: web::Data<PicoRuntime>,
+  payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+  let (version, rulename) = path.into_inner();
+  exec_rule(&rulename, Some(&version), &data_rt, payload).await
+}
+
+async fn rules(data: web::Data<PicoRuntime>) -> Result<HttpResponse, Error> {
+  let rules = data.rule_file_names();
   Ok(HttpResponse::Ok().json(rules))
 }
 
-async fn get_rule_by_name<'a>(
-  data: web::Data<Mutex<PicoRuntime<'a>>>,
+async fn get_rule_by_name(
+  req: HttpRequest,
+  data: web::Data<PicoRuntime>,
   rulename: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  let rt = data.lock().unwrap();
+  let accept = req
+    .headers()
+    .get(header::ACCEPT)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default();
 
-  let maybe_rulefile = rt.get_rule(&rulename);
+  let format = match RuleFormat::from_accept(accept) {
+    Some(format) => format,
+    None => {
+      warn!("unsupported Accept: {}", accept);
+      return HttpResponse::NotAcceptable().await;
+    }
+  };
+
+  let pico_rule = data.get_pico_rule(&rulename);
 
-  match maybe_rulefile {
-    Some(rulefile) => Ok(HttpResponse::Ok().json(rulefile)),
+  match pico_rule
+    .as_ref()
+    .and_then(|pico_rule| pico_rule.get_rulefile())
+  {
+    Some(rulefile) => match format.render(rulefile) {
+      Ok(document) => Ok(
+        HttpResponse::Ok()
+          .content_type(format.mime_type())
+          .body(document),
+      ),
+      Err(x) => {
+        error!("render failed {}", x);
+        HttpResponse::NotAcceptable().await
+      }
+    },
     None => HttpResponse::NotFound().await,
   }
 }
 
-async fn post_rule_by_name<'a>(
-  data: web::Data<Mutex<PicoRuntime<'a>>>,
+async fn post_rule_by_name(
+  data: web::Data<PicoRuntime>,
   rulename: web::Path<String>,
-  rulefile: web::Json<RuleFile>,
+  document: web::Json<PicoValue>,
+) -> Result<HttpResponse, Error> {
+  let uploaded = lint::parse_rulefile(&rulename, &document).and_then(|rulefile| {
+    info!("GOT a rulefile {}", rulefile);
+    data.upload_rule(&rulename, rulefile)
+  });
+
+  match uploaded {
+    Ok(uploaded) => Ok(HttpResponse::Ok().json(uploaded)),
+    Err(errors) => Ok(HttpResponse::UnprocessableEntity().json(LintResponse { errors })),
+  }
+}
+
+#[derive(Serialize)]
+struct LintResponse {
+  errors: Vec<Lint>,
+}
+
+async fn rule_schema() -> Result<HttpResponse, Error> {
+  Ok(HttpResponse::Ok().json(schema::rule_file_schema()))
+}
+
+async fn lookup_schema() -> Result<HttpResponse, Error> {
+  Ok(HttpResponse::Ok().json(schema::lookup_table_schema()))
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+  version: String,
+}
+
+async fn globals(data: web::Data<PicoRuntime>) -> Result<HttpResponse, Error> {
+  Ok(HttpResponse::Ok().json(data.globals()))
+}
+
+async fn versions(data: web::Data<PicoRuntime>) -> Result<HttpResponse, Error> {
+  Ok(HttpResponse::Ok().json(data.versions()))
+}
+
+async fn diff_versions(
+  data: web::Data<PicoRuntime>,
+  path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+  let (from, to) = path.into_inner();
+  match data.diff_versions(&from, &to) {
+    Ok(diff) => Ok(HttpResponse::Ok().json(diff)),
+    Err(x) => Ok(HttpResponse::NotFound().json(vec![x.to_string()])),
+  }
+}
+
+async fn rollback(
+  data: web::Data<PicoRuntime>,
+  version: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  info!("GOT a rulefile {}", rulefile);
+  match data.rollback(&version) {
+    Ok(version) => Ok(HttpResponse::Ok().json(VersionResponse { version })),
+    Err(x) => Ok(HttpResponse::NotFound().json(vec![x.to_string()])),
+  }
+}
+
+// re-reads the rule source, requests keep running against the old version until it is published
+async fn reload(data: web::Data<PicoRuntime>) -> Result<HttpResponse, Error> {
+  match data.reload() {
+    Ok(version) => Ok(HttpResponse::Ok().json(VersionResponse { version })),
+    Err(x) => {
+      error!("reload failed {}", x);
+      Ok(HttpResponse::InternalServerError().json(vec![x.to_string()]))
+    }
+  }
+}
+
+#[cfg(feature = "archive")]
+fn with_bundle(rt: PicoRuntime, bundle: &str) -> std::io::Result<PicoRuntime> {
+  let source = ArchiveSource::open(bundle)
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+  Ok(rt.set_rule_source(source))
+}
+
+#[cfg(not(feature = "archive"))]
+fn with_bundle(_rt: PicoRuntime, bundle: &str) -> std::io::Result<PicoRuntime> {
+  error!("--bundle {} requires the archive feature", bundle);
+  Err(std::io::Error::other("built without the archive feature"))
+}
+
+#[cfg(feature = "sqlite")]
+fn with_state(rt: PicoRuntime, database: &str) -> std::io::Result<PicoRuntime> {
+  let store = SqliteStore::open(database)
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+  Ok(rt.set_state_store(store))
+}
 
-  let mut rt = data.lock().unwrap();
-  rt.post_rule(&rulename, rulefile.into_inner());
-  HttpResponse::Ok().await
+#[cfg(not(feature = "sqlite"))]
+fn with_state(_rt: PicoRuntime, database: &str) -> std::io::Result<PicoRuntime> {
+  error!("--state {} requires the sqlite feature", database);
+  Err(std::io::Error::other("built without the sqlite feature"))
 }
 
 #[actix_web::main]
@@ -110,6 +540,21 @@ async fn main() -> std::io::Result<()> {
         .default_value("rules")
         .takes_value(true),
     )
+    .arg(Arg::with_name("bundle").long("bundle").takes_value(true))
+    .arg(Arg::with_name("state").long("state").takes_value(true))
+    .arg(Arg::with_name("globals").long("globals").takes_value(true))
+    .arg(Arg::with_name("forbid_dynamic_patterns").long("forbid-dynamic-patterns"))
+    .arg(
+      Arg::with_name("upload_namespaces")
+        .long("upload-namespaces")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("globals_env_prefix")
+        .long("globals-env-prefix")
+        .default_value(GLOBAL_ENV_PREFIX)
+        .takes_value(true),
+    )
     .arg(
       Arg::with_name("entry")
         .default_value("pico.rule.json")
@@ -145,21 +590,69 @@ async fn main() -> std::io::Result<()> {
     .to_string();
 
   // Create the Pico rules runtime using command line args
-  let rt = PicoRuntime::new()
-    .set_rules_directory(&rules_directory)
-    .initialise()
-    .set_default_rule(&entry_rule);
+  let rt = match matches.value_of("bundle") {
+    Some(bundle) => with_bundle(PicoRuntime::new(), bundle)?,
+    None => PicoRuntime::new().set_rules_directory(&rules_directory),
+  }
+  .set_default_rule(&entry_rule);
+
+  let rt = match matches.value_of("state") {
+    Some(database) => with_state(rt, database)?,
+    None => rt,
+  };
+
+  let rt = match matches.is_present("forbid_dynamic_patterns") {
+    true => rt.forbid_dynamic_patterns(),
+    false => rt,
+  };
+
+  // comma separated, uploads may also declare these namespaces
+  let rt = match matches.value_of("upload_namespaces") {
+    Some(namespaces) => {
+      rt.add_upload_namespaces(namespaces.split(',').map(|ns| ns.trim().to_string()))
+    }
+    None => rt,
+  };
+
+  // the environment overrides the globals file
+  let mut fixed_globals = match matches.value_of("globals") {
+    Some(filename) => globals_from_file(filename)
+      .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?,
+    None => Default::default(),
+  };
+  fixed_globals.extend(globals_from_env(
+    matches
+      .value_of("globals_env_prefix")
+      .unwrap_or(GLOBAL_ENV_PREFIX),
+  ));
+  let rt = rt.add_fixed_globals(fixed_globals).initialise();
 
-  let data = web::Data::new(Mutex::new(rt));
+  // shared by every worker without a lock, see PicoRuntime
+  let data = web::Data::new(rt);
 
   HttpServer::new(move || {
     App::new()
       .app_data(data.clone())
       .service(web::resource("/submit").route(web::post().to(submit_default)))
-      .service(web::scope("/submit/").service(submit_with_rulename))
+      .service(
+        web::scope("/submit/")
+          .service(submit_batch)
+          .service(submit_with_rulename),
+      )
       .service(web::resource("/rules").route(web::get().to(rules)))
       .route("/rule/{rulename}", web::get().to(get_rule_by_name))
       .route("/rule/{rulename}", web::post().to(post_rule_by_name))
+      .route("/schema", web::get().to(rule_schema))
+      .route("/schema/lookup", web::get().to(lookup_schema))
+      .route("/globals", web::get().to(globals))
+      .route("/versions", web::get().to(versions))
+      .route("/versions/{from}/diff/{to}", web::get().to(diff_versions))
+      .route("/versions/{version}/rollback", web::post().to(rollback))
+      .route("/reload", web::post().to(reload))
+      .route(
+        "/versions/{version}/submit/{rulename}",
+        web::post().to(submit_at_version),
+      )
   })
   .workers(32)
   .bind(binding_to)?
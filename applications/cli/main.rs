@@ -0,0 +1,570 @@
+use clap::{App as ClApp, Arg, ArgMatches};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use picolang::errors::RuntimeError;
+use picolang::lint::{self, Lint, Linter};
+use picolang::replay::{self, read_corpus, read_snapshot, write_snapshot};
+use picolang::rules::loaders::{read_document, RuleFormat};
+use picolang::runtime::PicoRuntime;
+use picolang::schema;
+use picolang::testing::{is_test_file, junit_xml, read_suite, run_suite, SuiteReport};
+use picolang::PicoValue;
+
+#[macro_use]
+extern crate log;
+
+// exit codes
+const EXIT_OK: i32 = 0;
+// a rule failed or finished with warnings
+const EXIT_FAILED: i32 = 1;
+// bad arguments, unreadable rules or input
+const EXIT_USAGE: i32 = 2;
+
+// inputs run in parallel at most this many at a time
+const BATCH_SIZE: usize = 256;
+
+fn schema_command(matches: &ArgMatches) -> i32 {
+  let generated = if matches.is_present("lookup") {
+    schema::lookup_table_schema()
+  } else {
+    schema::rule_file_schema()
+  };
+  print!("{}", schema::to_json(&generated));
+  EXIT_OK
+}
+
+/// the directory rules are loaded from and the rule to run from it,
+/// without --rules-dir the rule is a path and its directory is loaded
+fn rule_location(matches: &ArgMatches) -> (String, String) {
+  let rule = matches.value_of("rule").unwrap_or_default();
+  match matches.value_of("rules_dir") {
+    Some(directory) => (directory.to_string(), rule.to_string()),
+    None => {
+      let path = Path::new(rule);
+      let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+      };
+      let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+      (directory, name)
+    }
+  }
+}
+
+type Input = Result<serde_json::Value, String>;
+
+/// one JSON document or several, such as NDJSON, from the argument, a file or stdin,
+/// sent to `inputs` as each one is read
+fn read_inputs(matches: &ArgMatches, inputs: SyncSender<Input>) -> Result<(), String> {
+  let reader: Box<dyn Read + Send> = match (matches.value_of("json"), matches.value_of("input")) {
+    (Some(json), _) => Box::new(Cursor::new(json.to_string().into_bytes())),
+    (None, Some(filename)) if filename != "-" => {
+      Box::new(File::open(filename).map_err(|e| format!("{}: {}", filename, e))?)
+    }
+    _ => Box::new(std::io::stdin()),
+  };
+
+  thread::spawn(move || {
+    let documents = serde_json::Deserializer::from_reader(BufReader::new(reader))
+      .into_iter::<serde_json::Value>()
+      .enumerate();
+    for (n, input) in documents {
+      let input = input.map_err(|e| format!("input {}: {}", n + 1, e));
+      let failed = input.is_err();
+      // stops when the receiver has gone, a stream that failed to parse can not carry on
+      if inputs.send(input).is_err() || failed {
+        break;
+      }
+    }
+  });
+  Ok(())
+}
+
+/// the inputs already read, at least one unless they have all been run
+fn next_batch(inputs: &Receiver<Input>) -> Vec<Input> {
+  let mut batch: Vec<Input> = inputs.recv().into_iter().collect();
+  while batch.len() < BATCH_SIZE {
+    match inputs.try_recv() {
+      Ok(input) => batch.push(input),
+      Err(_) => break,
+    }
+  }
+  batch
+}
+
+/// every rule and lookup file in `directory`, and `rulename` must be one of them when given
+fn load_rules(directory: &str, rulename: Option<&str>) -> Option<PicoRuntime> {
+  let rt = PicoRuntime::new().set_rules_directory(directory);
+  if let Err(e) = rt.reload() {
+    eprintln!("failed to load rules from {}: {}", directory, e);
+    return None;
+  }
+  let rulename = match rulename {
+    Some(rulename) => rulename,
+    None => return Some(rt),
+  };
+  if !rt.has_rule(rulename) {
+    eprintln!("no rule {} in {}", rulename, directory);
+    return None;
+  }
+
+  // the rule and everything it includes must have loaded, a broken file would run as an empty one
+  let mut pending = vec![rulename.to_string()];
+  let mut seen = HashSet::new();
+  while let Some(name) = pending.pop() {
+    if !seen.insert(name.clone()) {
+      continue;
+    }
+    if let Some(rule) = rt.get_pico_rule(&name) {
+      if let Some(e) = rule.load_error() {
+        eprintln!("failed to load {} from {}: {}", name, directory, e);
+        return None;
+      }
+      pending.extend(rule.included_filenames());
+    }
+  }
+  Some(rt)
+}
+
+fn run_command(matches: &ArgMatches) -> i32 {
+  let (directory, rulename) = rule_location(matches);
+  let rt = match load_rules(&directory, Some(&rulename)) {
+    Some(rt) => rt,
+    None => return EXIT_USAGE,
+  };
+
+  let (sender, inputs) = sync_channel(BATCH_SIZE * 2);
+  if let Err(e) = read_inputs(matches, sender) {
+    eprintln!("{}", e);
+    return EXIT_USAGE;
+  }
+
+  // an input given as an argument is printed readably, the rest as one line each as they are run
+  let pretty = matches.is_present("json");
+  let version = rt.current();
+  let mut exit_code = EXIT_OK;
+  loop {
+    let batch = next_batch(&inputs);
+    if batch.is_empty() {
+      break;
+    }
+    let (documents, unreadable): (Vec<Input>, Vec<Input>) =
+      batch.into_iter().partition(Input::is_ok);
+    let documents: Vec<serde_json::Value> = documents.into_iter().filter_map(Result::ok).collect();
+    debug!("running {} with {} inputs", rulename, documents.len());
+    exit_code = exit_code.max(print_results(
+      rt.exec_batch_in(&version, &rulename, documents),
+      pretty,
+    ));
+    if let Some(Err(e)) = unreadable.into_iter().next() {
+      eprintln!("{}", e);
+      return EXIT_USAGE;
+    }
+  }
+  exit_code
+}
+
+fn print_results(
+  results: Vec<Result<HashMap<String, PicoValue>, RuntimeError>>,
+  pretty: bool,
+) -> i32 {
+  let mut exit_code = EXIT_OK;
+  let stdout = std::io::stdout();
+  let mut out = stdout.lock();
+  for result in results {
+    let final_ctx = match result {
+      Ok(final_ctx) => final_ctx,
+      Err(e) => {
+        eprintln!("{}", e);
+        exit_code = EXIT_FAILED;
+        continue;
+      }
+    };
+    if final_ctx.contains_key("warnings") {
+      exit_code = EXIT_FAILED;
+    }
+    let printed = match pretty {
+      true => serde_json::to_string_pretty(&final_ctx),
+      false => serde_json::to_string(&final_ctx),
+    };
+    match printed {
+      Ok(printed) => {
+        let _ = writeln!(out, "{}", printed);
+      }
+      Err(e) => {
+        eprintln!("{}", e);
+        exit_code = EXIT_FAILED;
+      }
+    }
+  }
+  // each batch is seen as soon as it is done, such as when reading from a pipe
+  let _ = out.flush();
+  exit_code
+}
+
+/// files named on the command line, directories are searched for the `wanted` files
+fn files_in(matches: &ArgMatches, wanted: fn(&str) -> bool) -> Result<Vec<String>, String> {
+  let mut files = Vec::new();
+  for path in matches.values_of("paths").into_iter().flatten() {
+    if !Path::new(path).is_dir() {
+      files.push(path.to_string());
+      continue;
+    }
+    let entries = std::fs::read_dir(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut found: Vec<String> = entries
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path().to_string_lossy().to_string())
+      .filter(|name| wanted(name))
+      .collect();
+    found.sort();
+    files.extend(found);
+  }
+  Ok(files)
+}
+
+/// --rules-dir, or the directory `file` is in
+fn rules_directory(matches: &ArgMatches, file: &str) -> String {
+  match matches.value_of("rules_dir") {
+    Some(directory) => directory.to_string(),
+    None => match Path::new(file).parent() {
+      Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().to_string(),
+      _ => ".".to_string(),
+    },
+  }
+}
+
+fn test_command(matches: &ArgMatches) -> i32 {
+  let files = match files_in(matches, is_test_file) {
+    Ok(files) => files,
+    Err(e) => {
+      eprintln!("{}", e);
+      return EXIT_USAGE;
+    }
+  };
+
+  // suites in the same directory share the rules loaded from it
+  let mut runtimes: HashMap<String, PicoRuntime> = HashMap::new();
+  let mut reports: Vec<SuiteReport> = Vec::new();
+  for file in &files {
+    let suite = match read_suite(file) {
+      Ok(suite) => suite,
+      Err(e) => {
+        eprintln!("{}", e);
+        return EXIT_USAGE;
+      }
+    };
+    let directory = rules_directory(matches, file);
+    if !runtimes.contains_key(&directory) {
+      match load_rules(&directory, None) {
+        Some(rt) => runtimes.insert(directory.clone(), rt),
+        None => return EXIT_USAGE,
+      };
+    }
+
+    let report = run_suite(&runtimes[&directory], file, &suite);
+    for case in &report.cases {
+      match (&case.error, case.passed()) {
+        (Some(error), _) => println!("ERROR  {} {}: {}", file, case.name, error),
+        (None, true) => println!("ok     {} {}", file, case.name),
+        (None, false) => {
+          println!("FAILED {} {}", file, case.name);
+          for difference in &case.differences {
+            println!("         {}", difference);
+          }
+        }
+      }
+    }
+    reports.push(report);
+  }
+
+  let cases: usize = reports.iter().map(|report| report.cases.len()).sum();
+  let failures: usize = reports.iter().map(|report| report.failures()).sum();
+  println!(
+    "{} suites, {} cases, {} failed",
+    reports.len(),
+    cases,
+    failures
+  );
+
+  if let Some(filename) = matches.value_of("junit") {
+    if let Err(e) = std::fs::write(filename, junit_xml(&reports)) {
+      eprintln!("{}: {}", filename, e);
+      return EXIT_USAGE;
+    }
+  }
+
+  match failures {
+    0 => EXIT_OK,
+    _ => EXIT_FAILED,
+  }
+}
+
+fn lint_command(matches: &ArgMatches) -> i32 {
+  let files = match files_in(matches, |name| RuleFormat::is_kind(name, "rule")) {
+    Ok(files) => files,
+    Err(e) => {
+      eprintln!("{}", e);
+      return EXIT_USAGE;
+    }
+  };
+  let mut linter = Linter::new();
+  if let Some(filename) = matches.value_of("input") {
+    match read_document::<serde_json::Value>(filename) {
+      Ok(input) => linter = linter.set_input(input),
+      Err(e) => {
+        eprintln!("{}: {}", filename, e);
+        return EXIT_USAGE;
+      }
+    }
+  }
+
+  let mut runtimes: HashMap<String, PicoRuntime> = HashMap::new();
+  let mut lints: Vec<Lint> = Vec::new();
+  for file in &files {
+    let directory = rules_directory(matches, file);
+    if !runtimes.contains_key(&directory) {
+      match load_rules(&directory, None) {
+        Some(rt) => runtimes.insert(directory.clone(), rt),
+        None => return EXIT_USAGE,
+      };
+    }
+    let rulename = Path::new(file)
+      .file_name()
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default();
+
+    let document = match read_document::<serde_json::Value>(file) {
+      Ok(document) => document,
+      Err(e) => {
+        eprintln!("{}: {}", file, e);
+        return EXIT_USAGE;
+      }
+    };
+    match lint::parse_rulefile(&rulename, &document) {
+      Ok(rulefile) => lints.extend(linter.lint_in(&runtimes[&directory], &rulename, &rulefile)),
+      Err(errors) => lints.extend(errors),
+    }
+  }
+
+  let errors = lints.iter().filter(|lint| lint.is_error()).count();
+  if matches.is_present("json") {
+    match serde_json::to_string_pretty(&lints) {
+      Ok(printed) => println!("{}", printed),
+      Err(e) => eprintln!("{}", e),
+    }
+  } else {
+    for lint in &lints {
+      println!("{}", lint);
+    }
+    println!(
+      "{} files, {} errors, {} warnings",
+      files.len(),
+      errors,
+      lints.len() - errors
+    );
+  }
+
+  match errors {
+    0 => EXIT_OK,
+    _ => EXIT_FAILED,
+  }
+}
+
+fn replay_command(matches: &ArgMatches) -> i32 {
+  let (directory, rulename) = rule_location(matches);
+  let rt = match load_rules(&directory, Some(&rulename)) {
+    Some(rt) => rt,
+    None => return EXIT_USAGE,
+  };
+  let corpus_file = matches.value_of("corpus").unwrap_or_default();
+  let corpus = match read_corpus(corpus_file) {
+    Ok(corpus) => corpus,
+    Err(e) => {
+      eprintln!("{}", e);
+      return EXIT_USAGE;
+    }
+  };
+  let outcomes = replay::record(&rt, &rulename, corpus.clone());
+
+  if let Some(snapshot) = matches.value_of("record") {
+    return match write_snapshot(snapshot, &outcomes) {
+      Ok(()) => {
+        println!("recorded {} outcomes to {}", outcomes.len(), snapshot);
+        EXIT_OK
+      }
+      Err(e) => {
+        eprintln!("{}", e);
+        EXIT_USAGE
+      }
+    };
+  }
+
+  // what the rules did before, from a snapshot or the rules in another directory
+  let before = match (matches.value_of("snapshot"), matches.value_of("against")) {
+    (Some(snapshot), _) => match read_snapshot(snapshot) {
+      Ok(before) => before,
+      Err(e) => {
+        eprintln!("{}", e);
+        return EXIT_USAGE;
+      }
+    },
+    (None, Some(against)) => match load_rules(against, Some(&rulename)) {
+      Some(baseline) => replay::record(&baseline, &rulename, corpus),
+      None => return EXIT_USAGE,
+    },
+    (None, None) => {
+      eprintln!("replay needs one of --record, --snapshot or --against");
+      return EXIT_USAGE;
+    }
+  };
+
+  let report = replay::compare(&before, &outcomes, matches.value_of("decision"));
+  if matches.is_present("json") {
+    match serde_json::to_string_pretty(&report) {
+      Ok(json) => println!("{}", json),
+      Err(e) => eprintln!("{}", e),
+    }
+  } else {
+    let shown = |value: &Option<serde_json::Value>| match value {
+      Some(value) => value.to_string(),
+      None => "nothing".to_string(),
+    };
+    for changed in &report.changed {
+      let decision = match changed.decision_changed {
+        true => "decision changed",
+        false => "changed",
+      };
+      println!("input {} {} {}", changed.number, decision, changed.input);
+      for change in &changed.changes {
+        println!(
+          "    {}: {} -> {}",
+          change.path,
+          shown(&change.before),
+          shown(&change.after)
+        );
+      }
+    }
+    println!(
+      "{} inputs, {} changed, {} decisions changed",
+      report.inputs,
+      report.changed.len(),
+      report.decisions_changed()
+    );
+  }
+
+  match report.decisions_changed() {
+    0 => EXIT_OK,
+    _ => EXIT_FAILED,
+  }
+}
+
+fn main() {
+  let app = ClApp::new("pico")
+    .version("0.1")
+    .about("pico rule language tools")
+    .subcommand(
+      ClApp::new("schema")
+        .about("prints the JSON Schema of rule files, or lookup files with --lookup")
+        .arg(Arg::with_name("lookup").long("lookup")),
+    )
+    .subcommand(
+      ClApp::new("run")
+        .about("runs a rule against JSON from an argument, --input or stdin and prints the final context")
+        .arg(Arg::with_name("rule").required(true).index(1))
+        .arg(Arg::with_name("json").index(2))
+        .arg(
+          Arg::with_name("input")
+            .long("input")
+            .short('i')
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("rules_dir")
+            .long("rules-dir")
+            .takes_value(true),
+        )
+        .arg(Arg::with_name("trace").long("trace")),
+    )
+    .subcommand(
+      ClApp::new("test")
+        .about("runs .test.json suites, given as files or directories holding them")
+        .arg(
+          Arg::with_name("paths")
+            .required(true)
+            .multiple(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("rules_dir")
+            .long("rules-dir")
+            .takes_value(true),
+        )
+        .arg(Arg::with_name("junit").long("junit").takes_value(true)),
+    )
+    .subcommand(
+      ClApp::new("replay")
+        .about("runs a JSON Lines corpus through a rule, recording a snapshot or comparing with one")
+        .arg(Arg::with_name("rule").required(true).index(1))
+        .arg(Arg::with_name("corpus").required(true).index(2))
+        .arg(
+          Arg::with_name("rules_dir")
+            .long("rules-dir")
+            .takes_value(true),
+        )
+        .arg(Arg::with_name("record").long("record").takes_value(true))
+        .arg(Arg::with_name("snapshot").long("snapshot").takes_value(true))
+        .arg(Arg::with_name("against").long("against").takes_value(true))
+        .arg(Arg::with_name("decision").long("decision").takes_value(true))
+        .arg(Arg::with_name("json").long("json")),
+    )
+    .subcommand(
+      ClApp::new("lint")
+        .about("checks rule files, given as files or directories holding them, without running them")
+        .arg(
+          Arg::with_name("paths")
+            .required(true)
+            .multiple(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("rules_dir")
+            .long("rules-dir")
+            .takes_value(true),
+        )
+        .arg(Arg::with_name("input").long("input").takes_value(true))
+        .arg(Arg::with_name("json").long("json")),
+    );
+
+  let matches = app.get_matches();
+
+  // --trace logs every step the rules take to stderr, the output stays on stdout
+  let mut logger = env_logger::Builder::from_default_env();
+  if let Some(("run", run_matches)) = matches.subcommand() {
+    if run_matches.is_present("trace") {
+      logger.filter_module("picolang", log::LevelFilter::Trace);
+    }
+  }
+  logger.init();
+
+  let exit_code = match matches.subcommand() {
+    Some(("schema", schema_matches)) => schema_command(schema_matches),
+    Some(("run", run_matches)) => run_command(run_matches),
+    Some(("test", test_matches)) => test_command(test_matches),
+    Some(("replay", replay_matches)) => replay_command(replay_matches),
+    Some(("lint", lint_matches)) => lint_command(lint_matches),
+    _ => {
+      eprintln!("no subcommand given, try --help");
+      EXIT_USAGE
+    }
+  };
+
+  std::process::exit(exit_code);
+}
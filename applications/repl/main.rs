@@ -0,0 +1,362 @@
+use clap::{App as ClApp, Arg};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::sync::Arc;
+
+use picolang::commands::execution::{ActionExecution, ConditionExecution, ValueExecution};
+use picolang::commands::Command;
+use picolang::conditions::Condition;
+use picolang::context::PicoContext;
+use picolang::rules::PicoRules;
+use picolang::runtime::PicoRuntime;
+use picolang::{PicoValue, ValueProducer};
+
+const HELP: &str = r#"
+  <json>              evaluate a condition, command or value, in that order of preference
+  :cond <json>        evaluate a condition
+  :do <json>          run a command, such as {"set": ["x", 1]}
+  :value <json>       evaluate a value producer, such as {"var": "x"}
+  :input <json|@file> set the input document, the context starts again
+  :use <rule>         evaluate in a rule file, for its lookups and namespaces
+  :run <rule>         run a rule and print the final context
+  :step <rule>        run a rule one root entry at a time, :next or an empty line runs the next
+  :locals             print the local variables
+  :ctx                print the whole context
+  :reset              start the context again with the same input
+  :rules              list the rule files
+  :quit
+"#;
+
+const COMMANDS: [&str; 14] = [
+  ":help", ":cond", ":do", ":value", ":input", ":use", ":run", ":step", ":next", ":locals", ":ctx",
+  ":reset", ":rules", ":quit",
+];
+
+/// completes REPL commands and the names of variables in the input and locals
+struct ReplHelper {
+  words: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+  type Candidate = String;
+
+  fn complete(
+    &self,
+    line: &str,
+    pos: usize,
+    _ctx: &Context<'_>,
+  ) -> rustyline::Result<(usize, Vec<String>)> {
+    let start = line[..pos]
+      .rfind(|c: char| c.is_whitespace() || c == '"')
+      .map(|i| i + 1)
+      .unwrap_or(0);
+    let prefix = &line[start..pos];
+    let candidates = self
+      .words
+      .iter()
+      .filter(|word| word.starts_with(prefix))
+      .cloned()
+      .collect();
+    Ok((start, candidates))
+  }
+}
+
+impl Hinter for ReplHelper {
+  type Hint = String;
+}
+impl Highlighter for ReplHelper {}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+// paths into the input as `var` reads them, `customer/address/city`
+fn input_paths(prefix: &str, value: &PicoValue, names: &mut Vec<String>) {
+  if let PicoValue::Object(map) = value {
+    for (key, value) in map {
+      let path = match prefix {
+        "" => key.clone(),
+        _ => format!("{}/{}", prefix, key),
+      };
+      names.push(path.clone());
+      input_paths(&path, value, names);
+    }
+  }
+}
+
+/// a rule being run one root entry at a time
+struct Stepping {
+  rule: Arc<PicoRules>,
+  next: usize,
+}
+
+struct Repl {
+  runtime: PicoRuntime,
+  input: PicoValue,
+  ctx: PicoContext,
+  // snippets are evaluated in this rule file, it decides which lookup tables they can use
+  rule: Arc<PicoRules>,
+  stepping: Option<Stepping>,
+}
+
+impl Repl {
+  fn new(runtime: PicoRuntime) -> Self {
+    let mut repl = Self {
+      runtime,
+      input: serde_json::json!({}),
+      ctx: PicoContext::new(),
+      rule: Arc::new(PicoRules::new("repl")),
+      stepping: None,
+    };
+    repl.reset();
+    repl
+  }
+
+  fn reset(&mut self) {
+    self.ctx = self.runtime.make_ctx(self.input.clone());
+    // snippets can read and write every namespace
+    let namespaces = self.runtime.namespaced_variables.read().unwrap();
+    self.ctx.ns_enter(namespaces.keys().cloned().collect());
+    self.stepping = None;
+  }
+
+  fn words(&self) -> Vec<String> {
+    let mut words: Vec<String> = COMMANDS.iter().map(|c| c.to_string()).collect();
+    words.extend(self.runtime.rule_file_names());
+    input_paths("", &self.input, &mut words);
+    words.extend(self.ctx.local_variables.keys().cloned());
+    words.sort();
+    words.dedup();
+    words
+  }
+
+  fn rule(&self, rulename: &str) -> Result<Arc<PicoRules>, String> {
+    self
+      .runtime
+      .get_pico_rule(rulename)
+      .ok_or_else(|| format!("no rule {}", rulename))
+  }
+
+  fn set_input(&mut self, argument: &str) -> Result<String, String> {
+    let document = match argument.strip_prefix('@') {
+      Some(filename) => std::fs::read_to_string(filename).map_err(|e| e.to_string())?,
+      None => argument.to_string(),
+    };
+    self.input = serde_json::from_str(&document).map_err(|e| e.to_string())?;
+    self.reset();
+    Ok(pretty(&self.input))
+  }
+
+  fn condition(&mut self, json: PicoValue) -> Result<String, String> {
+    let condition: Condition = serde_json::from_value(json).map_err(|e| e.to_string())?;
+    condition
+      .run_with_context(&self.rule, &self.runtime, &mut self.ctx)
+      .map(|result| result.to_string())
+      .map_err(|e| e.to_string())
+  }
+
+  fn command(&mut self, json: PicoValue) -> Result<String, String> {
+    let command: Command = serde_json::from_value(json).map_err(|e| e.to_string())?;
+    command
+      .run_with_context(&self.rule, &self.runtime, &mut self.ctx)
+      .map(|result| format!("{:?}", result))
+      .map_err(|e| e.to_string())
+  }
+
+  fn value(&mut self, json: PicoValue) -> Result<String, String> {
+    let value: ValueProducer = serde_json::from_value(json).map_err(|e| e.to_string())?;
+    value
+      .run_with_context(&self.rule, &self.runtime, &mut self.ctx)
+      .map(|result| pretty(&result))
+      .map_err(|e| e.to_string())
+  }
+
+  fn evaluate(&mut self, snippet: &str) -> Result<String, String> {
+    let json: PicoValue = serde_json::from_str(snippet).map_err(|e| e.to_string())?;
+    if serde_json::from_value::<Condition>(json.clone()).is_ok() {
+      self.condition(json)
+    } else if serde_json::from_value::<Command>(json.clone()).is_ok() {
+      self.command(json)
+    } else {
+      self.value(json)
+    }
+  }
+
+  fn run(&mut self, rulename: &str) -> Result<String, String> {
+    self.reset();
+    let final_ctx = self
+      .runtime
+      .exec_rule_with_context(rulename, &mut self.ctx)
+      .map_err(|e| e.to_string())?;
+    Ok(pretty(&serde_json::json!(final_ctx)))
+  }
+
+  fn step(&mut self, rulename: &str) -> Result<String, String> {
+    let rule = self.rule(rulename)?;
+    self.reset();
+    self.stepping = Some(Stepping { rule, next: 0 });
+    Ok(self.upcoming())
+  }
+
+  fn upcoming(&self) -> String {
+    match &self.stepping {
+      Some(stepping) => match stepping.rule.steps().get(stepping.next) {
+        Some(step) => format!(
+          "next {}/{}: {}",
+          stepping.next + 1,
+          stepping.rule.steps().len(),
+          serde_json::to_string(step).unwrap_or_default()
+        ),
+        None => "next: fini".to_string(),
+      },
+      None => "not stepping, use :step <rule>".to_string(),
+    }
+  }
+
+  fn next(&mut self) -> Result<String, String> {
+    let (rule, next) = match &self.stepping {
+      Some(stepping) => (stepping.rule.clone(), stepping.next),
+      None => return Err(self.upcoming()),
+    };
+    let version = self.runtime.current();
+    match rule.steps().get(next) {
+      Some(step) if self.ctx.stopped().is_none() => {
+        rule.run_step(&self.runtime, &version.rules, &mut self.ctx, step);
+        if let Some(stepping) = self.stepping.as_mut() {
+          stepping.next += 1;
+        }
+        Ok(format!(
+          "locals: {}\n{}",
+          serde_json::json!(self.ctx.local_variables),
+          self.upcoming()
+        ))
+      }
+      // the root is done, or stopped early
+      _ => {
+        rule.run_fini(&self.runtime, &mut self.ctx);
+        self.stepping = None;
+        Ok(pretty(&serde_json::json!(self.ctx.get_final_ctx())))
+      }
+    }
+  }
+
+  fn handle(&mut self, line: &str) -> Result<String, String> {
+    let (command, argument) = match line.split_once(char::is_whitespace) {
+      Some((command, argument)) => (command, argument.trim()),
+      None => (line, ""),
+    };
+    let json = || serde_json::from_str::<PicoValue>(argument).map_err(|e| e.to_string());
+    match command {
+      "" | ":next" => self.next(),
+      ":help" => Ok(HELP.to_string()),
+      ":cond" => self.condition(json()?),
+      ":do" => self.command(json()?),
+      ":value" => self.value(json()?),
+      ":input" => self.set_input(argument),
+      ":use" => {
+        self.rule = self.rule(argument)?;
+        Ok(format!("evaluating in {}", argument))
+      }
+      ":run" => self.run(argument),
+      ":step" => self.step(argument),
+      ":locals" => Ok(pretty(&serde_json::json!(self.ctx.local_variables))),
+      ":ctx" => Ok(pretty(&serde_json::json!(self.ctx.get_final_ctx()))),
+      ":reset" => {
+        self.reset();
+        Ok("context reset".to_string())
+      }
+      ":rules" => Ok(self.runtime.rule_file_names().join("\n")),
+      _ if command.starts_with(':') => Err(format!("unknown command {}, try :help", command)),
+      _ => self.evaluate(line),
+    }
+  }
+}
+
+fn pretty(value: &PicoValue) -> String {
+  serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+fn main() -> rustyline::Result<()> {
+  env_logger::init();
+
+  let app = ClApp::new("pico-repl")
+    .version("0.1")
+    .about("evaluates pico conditions, commands and values against an input document")
+    .arg(
+      Arg::with_name("rules_dir")
+        .long("rules-dir")
+        .default_value("rules")
+        .takes_value(true),
+    )
+    .arg(Arg::with_name("input").long("input").takes_value(true));
+  let matches = app.get_matches();
+
+  let directory = matches.value_of("rules_dir").unwrap_or("rules");
+  let runtime = PicoRuntime::new().set_rules_directory(directory);
+  if let Err(e) = runtime.reload() {
+    eprintln!("failed to load rules from {}: {}", directory, e);
+    std::process::exit(2);
+  }
+
+  let mut repl = Repl::new(runtime);
+  if let Some(input) = matches.value_of("input") {
+    if let Err(e) = repl.set_input(&format!("@{}", input)) {
+      eprintln!("{}: {}", input, e);
+    }
+  }
+
+  let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+  editor.set_helper(Some(ReplHelper {
+    words: repl.words(),
+  }));
+  let history = std::env::var("HOME")
+    .map(|home| format!("{}/.pico_history", home))
+    .ok();
+  if let Some(history) = &history {
+    // there is no history the first time
+    let _ = editor.load_history(history);
+  }
+
+  println!(
+    "loaded {} rules from {}, :help for commands",
+    repl.runtime.rule_file_names().len(),
+    directory
+  );
+  loop {
+    let prompt = match &repl.stepping {
+      Some(_) => "pico step> ",
+      None => "pico> ",
+    };
+    match editor.readline(prompt) {
+      Ok(line) => {
+        let line = line.trim();
+        if line == ":quit" {
+          break;
+        }
+        if !line.is_empty() {
+          editor.add_history_entry(line)?;
+        }
+        if line.is_empty() && repl.stepping.is_none() {
+          continue;
+        }
+        match repl.handle(line) {
+          Ok(output) => println!("{}", output),
+          Err(e) => println!("error: {}", e),
+        }
+        if let Some(helper) = editor.helper_mut() {
+          helper.words = repl.words();
+        }
+      }
+      Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+      Err(e) => return Err(e),
+    }
+  }
+
+  if let Some(history) = &history {
+    editor.save_history(history)?;
+  }
+  Ok(())
+}
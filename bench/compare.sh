@@ -0,0 +1,37 @@
+#!/bin/sh
+# Times `pico run` over the same generated inputs with the rules in bench/rules, built at two revisions.
+#
+#   bench/compare.sh <before> [after] [inputs]
+#
+# after defaults to HEAD and inputs to 100000, each revision is built in release mode in its own worktree.
+set -e
+
+before=$1
+after=${2:-HEAD}
+inputs=${3:-100000}
+[ -n "$before" ] || { echo "usage: $0 <before> [after] [inputs]" >&2; exit 2; }
+
+root=$(git rev-parse --show-toplevel)
+work=$root/target/bench
+mkdir -p "$work"
+
+corpus=$work/inputs.ndjson
+i=0
+: > "$corpus"
+while [ $i -lt "$inputs" ]; do
+  echo "{\"email\": \"user$i@example.com\", \"phone\": \"+44 20 7946 $i\", \"postcode\": \"SW1A 1AA\", \"name\": \"Ada Lovelace\"}"
+  i=$((i + 1))
+done >> "$corpus"
+
+for rev in "$before" "$after"; do
+  tree=$work/$(git rev-parse --short "$rev")
+  [ -d "$tree" ] || git worktree add --detach "$tree" "$rev" > /dev/null
+  cargo build --quiet --release --bin pico --manifest-path "$tree/Cargo.toml" --target-dir "$work/target-$(basename "$tree")"
+  pico=$work/target-$(basename "$tree")/release/pico
+  for run in 1 2 3; do
+    start=$(date +%s.%N)
+    "$pico" run --rules-dir "$root/bench/rules" match.rule.json < "$corpus" > /dev/null
+    end=$(date +%s.%N)
+    echo "$rev run $run: $(echo "$start $end" | awk -v n="$inputs" '{ printf "%.2fs, %.0f inputs/s", $2 - $1, n / ($2 - $1) }')"
+  done
+done
@@ -3,19 +3,22 @@ pub mod execution;
 pub mod flow_control;
 pub mod logging;
 pub mod setting;
+pub mod state;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ActionExecution, ActionResult, ActionValue};
 use crate::commands::flow_control::{BreakToCommand, IfThenElse, StopCommand};
 use crate::commands::logging::{DebugLog, Log};
-use crate::commands::setting::SetCommand;
+use crate::commands::setting::{SetCommand, SetGlobal};
+use crate::commands::state::{StateDelete, StateExpire, StateIncr, StateSet};
 use crate::context::PicoContext;
 //use crate::state::PicoState;
 use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Command {
     Log(Log),
@@ -24,6 +27,11 @@ pub enum Command {
     BreakTo(BreakToCommand),
     Stop(StopCommand),
     Set(SetCommand),
+    SetGlobal(SetGlobal),
+    StateSet(StateSet),
+    StateIncr(StateIncr),
+    StateExpire(StateExpire),
+    StateDelete(StateDelete),
 }
 impl ActionExecution for Command {
     fn run_with_context(
@@ -40,11 +48,16 @@ impl ActionExecution for Command {
             Command::BreakTo(bto) => bto.run_with_context(pico_rules, runtime, ctx),
             Command::Stop(sto) => sto.run_with_context(pico_rules, runtime, ctx),
             Command::Set(se) => se.run_with_context(pico_rules, runtime, ctx),
+            Command::SetGlobal(sg) => sg.run_with_context(pico_rules, runtime, ctx),
+            Command::StateSet(state) => state.run_with_context(pico_rules, runtime, ctx),
+            Command::StateIncr(state) => state.run_with_context(pico_rules, runtime, ctx),
+            Command::StateExpire(state) => state.run_with_context(pico_rules, runtime, ctx),
+            Command::StateDelete(state) => state.run_with_context(pico_rules, runtime, ctx),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct PopLocals {
     pop_locals: bool,
 }
@@ -61,7 +74,7 @@ impl ActionExecution for PopLocals {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum FiniCommand {
     Log(Log),
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ActionExecution, ActionResult, ActionValue};
@@ -6,7 +7,7 @@ use crate::context::PicoContext;
 use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Action {
     Command(Command),
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::action::Action;
@@ -8,11 +9,12 @@ use crate::context::PicoContext;
 use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 
+use std::sync::OnceLock;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct StopCommand {
-    stop: String,
+    pub(crate) stop: String,
 }
 impl ActionExecution for StopCommand {
     fn run_with_context(
@@ -26,9 +28,9 @@ impl ActionExecution for StopCommand {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct BreakToCommand {
-    r#break: uuid::Uuid,
+    pub(crate) r#break: uuid::Uuid,
 }
 impl ActionExecution for BreakToCommand {
     fn run_with_context(
@@ -42,20 +44,31 @@ impl ActionExecution for BreakToCommand {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct IfThenElse {
-    r#if: Condition,
-    r#then: Action,
-    r#else: Option<Action>,
+    pub(crate) r#if: Condition,
+    pub(crate) r#then: Action,
+    pub(crate) r#else: Option<Action>,
 
     #[serde(default = "IfThenElse::default_uuid")]
-    uuid: uuid::Uuid,
+    // the default is random, so it is left out of the schema
+    #[schemars(skip_serializing_if = "IfThenElse::is_generated")]
+    pub(crate) uuid: uuid::Uuid,
+
+    /// the result of a condition that only compares literals, found when the rule file is installed
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) folded: OnceLock<bool>,
 }
 impl IfThenElse {
     fn default_uuid() -> uuid::Uuid {
         trace!("assigning default uuid");
         Uuid::new_v4()
     }
+
+    fn is_generated(_uuid: &uuid::Uuid) -> bool {
+        true
+    }
 }
 
 impl ActionExecution for IfThenElse {
@@ -66,7 +79,10 @@ impl ActionExecution for IfThenElse {
         ctx: &mut PicoContext,
     ) -> ActionResult {
         info!("running ITE -> {:?}", self.uuid);
-        let if_result: bool = self.r#if.run_with_context(pico_rules, runtime, ctx)?;
+        let if_result: bool = match self.folded.get() {
+            Some(folded) => *folded,
+            None => self.r#if.run_with_context(pico_rules, runtime, ctx)?,
+        };
 
         match if_result {
             true => self.then.run_with_context(pico_rules, runtime, ctx),
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ActionExecution, ActionResult, ActionValue};
@@ -10,7 +11,7 @@ use crate::runtime::PicoRuntime;
 //use std::result;
 use tinytemplate::TinyTemplate;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Log {
     log: String,
 }
@@ -27,7 +28,7 @@ impl ActionExecution for Log {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct DebugLog {
     debug: String,
 
@@ -38,6 +39,10 @@ impl DebugLog {
     fn default_tt() -> String {
         "TTT".to_string()
     }
+
+    pub(crate) fn template(&self) -> &str {
+        &self.debug
+    }
 }
 impl ActionExecution for DebugLog {
     fn run_with_context(
@@ -46,6 +51,12 @@ impl ActionExecution for DebugLog {
         _runtime: &PicoRuntime,
         ctx: &mut PicoContext,
     ) -> ActionResult {
+        // nothing would be logged, skip building the template and copying the variables
+        if !log_enabled!(log::Level::Debug) {
+            return Ok(ActionValue::Continue);
+        }
+
+        // parsed per render, a TinyTemplate borrows its text and is not Sync so the rule can not keep one
         let mut tt = TinyTemplate::new();
         trace!("Building tiny template");
 
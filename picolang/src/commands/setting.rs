@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ActionExecution, ActionResult, ActionValue, ValueExecution};
@@ -7,17 +8,17 @@ use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 use crate::values::{Extract, PicoValue, ValueProducer};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Settable {
     ValueProducing(String, ValueProducer),
     Extractor(Extract),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct SetCommand {
-    set: Settable,
-    namespaces: Option<Vec<String>>, //namespaces that the variable will be available in
+    pub(crate) set: Settable,
+    pub(crate) namespaces: Option<Vec<String>>, //namespaces that the variable will be available in
 }
 impl ActionExecution for SetCommand {
     fn run_with_context(
@@ -56,7 +57,7 @@ impl ActionExecution for SetCommand {
                     None => {}
                     Some(requested_namespaces) => {
                         for ns in requested_namespaces {
-                            if pico_rules.is_ns_allowed(ns) {
+                            if ctx.ns_allowed(ns) {
                                 ctx.ns_set(ns, var_name, &produced_value);
                             } else {
                                 warn!("namespace {}, access denied for {}", ns, var_name);
@@ -72,3 +73,22 @@ impl ActionExecution for SetCommand {
         Ok(ActionValue::Continue)
     }
 }
+
+/// `{"set_global": [name, value]}`, fails unless the runtime has mutable globals
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SetGlobal {
+    pub(crate) set_global: (String, ValueProducer),
+}
+impl ActionExecution for SetGlobal {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ActionResult {
+        let (name, value_producer) = &self.set_global;
+        let value = value_producer.run_with_context(pico_rules, runtime, ctx)?;
+        runtime.global_set(name, &value)?;
+        Ok(ActionValue::Continue)
+    }
+}
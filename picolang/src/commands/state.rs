@@ -0,0 +1,170 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::commands::execution::{ActionExecution, ActionResult, ActionValue, ValueExecution};
+use crate::context::PicoContext;
+use crate::errors::PicoError;
+use crate::rules::PicoRules;
+use crate::runtime::PicoRuntime;
+use crate::values::{PicoValue, ValueProducer};
+
+/// the store key a `ValueProducer` names, after checking the namespace can be used
+pub(crate) fn state_key(
+    ns: &str,
+    key: &ValueProducer,
+    pico_rules: &PicoRules,
+    runtime: &PicoRuntime,
+    ctx: &mut PicoContext,
+) -> Result<String, PicoError> {
+    if !ctx.ns_allowed(ns) {
+        return Err(PicoError::NamespaceDenied {
+            namespace: ns.to_string(),
+        });
+    }
+    match key.run_with_context(pico_rules, runtime, ctx)? {
+        PicoValue::String(s) => Ok(s),
+        other => Ok(other.to_string()),
+    }
+}
+
+/// `{"state_set": [namespace, key, value], "ttl": seconds}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateSet {
+    pub(crate) state_set: (String, ValueProducer, ValueProducer),
+    /// seconds until the value expires
+    pub(crate) ttl: Option<u64>,
+}
+impl ActionExecution for StateSet {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ActionResult {
+        let (ns, key, value) = &self.state_set;
+        let key = state_key(ns, key, pico_rules, runtime, ctx)?;
+        let value = value.run_with_context(pico_rules, runtime, ctx)?;
+
+        debug!("state {}::{} = {}", ns, key, value);
+        runtime
+            .state()
+            .set(ns, &key, &value, self.ttl.map(Duration::from_secs))?;
+        Ok(ActionValue::Continue)
+    }
+}
+
+/// `{"state_incr": [namespace, key], "by": 1, "ttl": seconds, "as": local}`,
+/// the ttl only applies when the counter starts
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateIncr {
+    pub(crate) state_incr: (String, ValueProducer),
+    #[serde(default = "StateIncr::default_by")]
+    pub(crate) by: i64,
+    /// seconds until a new counter expires
+    pub(crate) ttl: Option<u64>,
+    /// local variable to hold the new count
+    pub(crate) r#as: Option<String>,
+}
+impl StateIncr {
+    fn default_by() -> i64 {
+        1
+    }
+}
+impl ActionExecution for StateIncr {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ActionResult {
+        let (ns, key) = &self.state_incr;
+        let key = state_key(ns, key, pico_rules, runtime, ctx)?;
+
+        let count = runtime
+            .state()
+            .incr(ns, &key, self.by, self.ttl.map(Duration::from_secs))?;
+        debug!("state {}::{} counted {}", ns, key, count);
+
+        if let Some(local) = &self.r#as {
+            ctx.local_set(local, &PicoValue::from(count));
+        }
+        Ok(ActionValue::Continue)
+    }
+}
+
+/// `{"state_expire": [namespace, key, seconds]}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateExpire {
+    pub(crate) state_expire: (String, ValueProducer, u64),
+}
+impl ActionExecution for StateExpire {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ActionResult {
+        let (ns, key, seconds) = &self.state_expire;
+        let key = state_key(ns, key, pico_rules, runtime, ctx)?;
+
+        if !runtime
+            .state()
+            .expire(ns, &key, Duration::from_secs(*seconds))?
+        {
+            debug!("state {}::{} not set, nothing to expire", ns, key);
+        }
+        Ok(ActionValue::Continue)
+    }
+}
+
+/// `{"state_del": [namespace, key]}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateDelete {
+    pub(crate) state_del: (String, ValueProducer),
+}
+impl ActionExecution for StateDelete {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ActionResult {
+        let (ns, key) = &self.state_del;
+        let key = state_key(ns, key, pico_rules, runtime, ctx)?;
+
+        runtime.state().delete(ns, &key)?;
+        Ok(ActionValue::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn state_counts_across_requests() {
+        let rt = test_runtime(&[(
+            "logins.rule.json",
+            r#"{
+                "namespaces": ["logins"],
+                "root": [
+                    {"state_incr": ["logins", {"var": "user"}], "ttl": 600, "as": "failures"},
+                    {"if": {"gt": [{"var": "failures"}, 2]}, "then": {"set": ["blocked", true]}},
+                    {"set": ["seen", {"state_get": ["logins", {"var": "user"}], "default": 0}]}
+                ]
+            }"#,
+        )]);
+        let attempt =
+            |user: &str| test_run(&rt, "logins.rule.json", json!({ "user": user })).unwrap();
+
+        for _ in 0..2 {
+            assert!(attempt("bob")["output"].get("blocked").is_none());
+        }
+        let third = attempt("bob");
+        assert_eq!(third["output"]["blocked"], json!(true));
+        assert_eq!(third["output"]["seen"], json!(3));
+        assert_eq!(attempt("alice")["output"]["seen"], json!(1));
+    }
+}
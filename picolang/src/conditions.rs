@@ -1,15 +1,20 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub mod compare;
 pub mod existence;
 pub mod logic;
 pub mod matching;
+pub mod rate;
+pub mod table;
 
 use crate::commands::execution::{ConditionExecution, ConditionResult};
 use crate::conditions::compare::{Eq, GreaterThan, LessThan};
 use crate::conditions::existence::{VarExistsCondition, VarMissingCondition};
 use crate::conditions::logic::{And, Not, Or};
 use crate::conditions::matching::{Match, RegMatch, StartsWith};
+use crate::conditions::rate::Rate;
+use crate::conditions::table::InTable;
 
 use crate::context::PicoContext;
 use crate::errors::PicoError;
@@ -18,7 +23,7 @@ use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 //use crate::values::{PicoValue, Var};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Condition {
     And(And),
@@ -32,6 +37,8 @@ pub enum Condition {
     VarExists(VarExistsCondition),
     VarMissing(VarMissingCondition),
     Not(Not),
+    Rate(Rate),
+    InTable(InTable),
 }
 
 impl ConditionExecution for Condition {
@@ -56,6 +63,9 @@ impl ConditionExecution for Condition {
 
             Condition::VarExists(ve) => ve.run_with_context(pico_rules, runtime, ctx),
             Condition::VarMissing(vm) => vm.run_with_context(pico_rules, runtime, ctx),
+
+            Condition::Rate(rate) => rate.run_with_context(pico_rules, runtime, ctx),
+            Condition::InTable(in_table) => in_table.run_with_context(pico_rules, runtime, ctx),
         };
 
         match condition_result {
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ConditionExecution, ConditionResult, ValueExecution};
@@ -9,9 +10,9 @@ use crate::runtime::PicoRuntime;
 //use crate::values::{PicoValue, Var};
 use crate::{PicoValue, ValueProducer};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Eq {
-    eq: (ValueProducer, ValueProducer),
+    pub(crate) eq: (ValueProducer, ValueProducer),
 }
 impl ConditionExecution for Eq {
     fn run_with_context(
@@ -30,9 +31,9 @@ impl ConditionExecution for Eq {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct GreaterThan {
-    gt: (ValueProducer, ValueProducer),
+    pub(crate) gt: (ValueProducer, ValueProducer),
 }
 impl ConditionExecution for GreaterThan {
     fn run_with_context(
@@ -60,9 +61,9 @@ impl ConditionExecution for GreaterThan {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct LessThan {
-    lt: (ValueProducer, ValueProducer),
+    pub(crate) lt: (ValueProducer, ValueProducer),
 }
 impl ConditionExecution for LessThan {
     fn run_with_context(
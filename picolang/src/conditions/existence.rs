@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::de::Visitor;
 use serde::{Deserialize, Serialize};
 
@@ -12,7 +13,7 @@ use regex::Regex;
  * existance or not of variables in the context
  */
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum VarExistence {
     SingleVar(String),
@@ -75,9 +76,9 @@ impl<'de> Deserialize<'de> for VarExistence {
 }
 
 /// Checks for the existence of a variable
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct VarExistsCondition {
-    exists: VarExistence,
+    pub(crate) exists: VarExistence,
 }
 
 impl ConditionExecution for VarExistsCondition {
@@ -109,9 +110,9 @@ impl ConditionExecution for VarExistsCondition {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct VarMissingCondition {
-    missing: String,
+    pub(crate) missing: String,
 }
 impl ConditionExecution for VarMissingCondition {
     fn run_with_context(
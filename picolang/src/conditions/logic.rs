@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ConditionExecution, ConditionResult};
@@ -12,9 +13,9 @@ use crate::runtime::PicoRuntime;
  * condition collections
  */
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct And {
-    and: Vec<Condition>,
+    pub(crate) and: Vec<Condition>,
 }
 
 impl ConditionExecution for And {
@@ -35,9 +36,9 @@ impl ConditionExecution for And {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Or {
-    or: Vec<Condition>,
+    pub(crate) or: Vec<Condition>,
 }
 impl ConditionExecution for Or {
     fn run_with_context(
@@ -60,9 +61,9 @@ impl ConditionExecution for Or {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Not {
-    not: Box<Condition>,
+    pub(crate) not: Box<Condition>,
 }
 impl ConditionExecution for Not {
     fn run_with_context(
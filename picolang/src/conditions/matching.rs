@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::commands::execution::{ConditionExecution, ConditionResult, ValueExecution};
@@ -10,15 +11,21 @@ use crate::runtime::PicoRuntime;
 use crate::{PicoValue, ValueProducer};
 
 use regex::Regex;
+use std::sync::OnceLock;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RegMatchInternal(#[serde(with = "serde_regex")] Regex, ValueProducer);
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RegMatchInternal(
+    #[serde(with = "serde_regex")]
+    #[schemars(with = "String")]
+    pub(crate) Regex,
+    pub(crate) ValueProducer,
+);
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct RegMatch {
     //    #[serde(with = "serde_regex")]
     //    regmatch: Regex,
-    regmatch: RegMatchInternal, //    with: ValueProducer,
+    pub(crate) regmatch: RegMatchInternal, //    with: ValueProducer,
 }
 
 impl ConditionExecution for RegMatch {
@@ -53,9 +60,9 @@ impl ConditionExecution for RegMatch {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct StartsWith {
-    match_start: (ValueProducer, ValueProducer), // needle, haystack
+    pub(crate) match_start: (ValueProducer, ValueProducer), // needle, haystack
 }
 impl ConditionExecution for StartsWith {
     fn run_with_context(
@@ -89,9 +96,14 @@ impl ConditionExecution for StartsWith {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Match {
-    r#match: (ValueProducer, ValueProducer),
+    pub(crate) r#match: (ValueProducer, ValueProducer),
+
+    /// a literal pattern, compiled when the rule file is installed
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) pattern: OnceLock<Regex>,
 }
 impl ConditionExecution for Match {
     fn run_with_context(
@@ -102,13 +114,16 @@ impl ConditionExecution for Match {
     ) -> ConditionResult {
         info!("running match");
         let lhs = self.r#match.0.run_with_context(pico_rules, runtime, ctx)?;
+        if let (Some(re), PicoValue::String(ls)) = (self.pattern.get(), &lhs) {
+            return Ok(re.is_match(ls));
+        }
         let rhs = self.r#match.1.run_with_context(pico_rules, runtime, ctx)?;
 
         match (&lhs, &rhs) {
             (PicoValue::String(ls), PicoValue::String(rs)) => {
-                let re = Regex::new(&rs).unwrap();
-                let b = re.is_match(&ls);
-                Ok(b)
+                let dynamic = !matches!(self.r#match.1, ValueProducer::LiteralString(_));
+                let re = runtime.regex(rs, dynamic)?;
+                Ok(re.is_match(ls))
             }
             _ => Err(PicoError::IncompatibleComparison(lhs, rhs)),
         }
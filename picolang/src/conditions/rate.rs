@@ -0,0 +1,123 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::execution::{ConditionExecution, ConditionResult, ValueExecution};
+use crate::context::PicoContext;
+use crate::rules::PicoRules;
+use crate::runtime::PicoRuntime;
+use crate::values::{PicoValue, ValueProducer};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RateAlgorithm {
+    /// counts every hit within the last window
+    #[default]
+    SlidingWindow,
+    /// allows bursts of up to the limit, refilling at limit per window
+    TokenBucket,
+}
+
+/// `{"rate": [key, limit, seconds]}` records a hit for key and is true when
+/// key has been seen more than limit times within the window
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Rate {
+    pub(crate) rate: (ValueProducer, u64, u64),
+    #[serde(default)]
+    pub(crate) algorithm: RateAlgorithm,
+    /// counters are shared by rate conditions with the same name, limit, window and algorithm,
+    /// the name is the rule file name by default
+    pub(crate) name: Option<String>,
+}
+
+impl ConditionExecution for Rate {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ConditionResult {
+        let (key, limit, seconds) = &self.rate;
+        let key = match key.run_with_context(pico_rules, runtime, ctx)? {
+            PicoValue::String(s) => s,
+            other => other.to_string(),
+        };
+        let name = self
+            .name
+            .as_deref()
+            .unwrap_or_else(|| pico_rules.get_rulename());
+        // a per minute and a per day limit on the same key each keep their own counter
+        let counter = format!(
+            "{}|{:?}|{}|{}|{}",
+            name, self.algorithm, limit, seconds, key
+        );
+
+        let exceeded = runtime.rate_exceeded(&counter, self.algorithm, *limit, *seconds);
+        debug!(
+            "rate {} limit {} per {}s exceeded={}",
+            counter, limit, seconds, exceeded
+        );
+        Ok(exceeded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime, ManualClock};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn rate_condition_uses_the_runtime_clock() {
+        let clock = Arc::new(ManualClock::new(0));
+        let rt = test_runtime(&[(
+            "rate.rule.json",
+            r#"{"root": [
+                {"if": {"rate": [{"var": "card"}, 2, 60]}, "then": {"set": ["flagged", true]}}
+            ]}"#,
+        )])
+        .set_clock(clock.clone());
+
+        let flagged = |card: &str| {
+            let result = test_run(&rt, "rate.rule.json", json!({ "card": card })).unwrap();
+            result["output"].get("flagged").is_some()
+        };
+        assert!(!flagged("a"));
+        clock.advance(Duration::from_secs(30));
+        assert!(!flagged("a"));
+        assert!(flagged("a"));
+        assert!(!flagged("b"));
+
+        // the first hit has left the window, the two after it are still in it
+        clock.advance(Duration::from_secs(31));
+        assert!(flagged("a"));
+        clock.advance(Duration::from_secs(61));
+        assert!(!flagged("a"));
+    }
+
+    #[test]
+    fn limits_on_the_same_key_count_apart() {
+        let clock = Arc::new(ManualClock::new(0));
+        let rt = test_runtime(&[(
+            "rate.rule.json",
+            r#"{"root": [
+                {"if": {"rate": [{"var": "card"}, 2, 60]}, "then": {"set": ["minute", true]}},
+                {"if": {"rate": [{"var": "card"}, 4, 86400]}, "then": {"set": ["day", true]}}
+            ]}"#,
+        )])
+        .set_clock(clock.clone());
+
+        let flagged = |limit: &str| {
+            let result = test_run(&rt, "rate.rule.json", json!({"card": "a"})).unwrap();
+            result["output"].get(limit).is_some()
+        };
+        assert!(!flagged("minute"));
+        assert!(!flagged("minute"));
+        assert!(flagged("minute"));
+
+        clock.advance(Duration::from_secs(61));
+        assert!(!flagged("day"));
+        // the fifth hit of the day, the minute window has only seen one
+        assert!(flagged("day"));
+    }
+}
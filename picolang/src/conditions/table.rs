@@ -0,0 +1,75 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::execution::{ConditionExecution, ConditionResult, ValueExecution};
+use crate::context::PicoContext;
+use crate::rules::lookups::key_part;
+use crate::rules::PicoRules;
+use crate::runtime::PicoRuntime;
+use crate::values::LookupKey;
+
+/// `{"in_table": [table, key]}` is true when the key has an entry, ignoring the table's default
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct InTable {
+    pub(crate) in_table: (String, LookupKey),
+}
+
+impl ConditionExecution for InTable {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ConditionResult {
+        let (table_name, key) = &self.in_table;
+        let key = key_part(&key.run_with_context(pico_rules, runtime, ctx)?);
+
+        let table = pico_rules.resolve_table(runtime, ctx, table_name)?;
+        Ok(table.get(&key).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn lookup_misses_are_visible() {
+        let rt = test_runtime(&[(
+            "cards.rule.json",
+            r#"{
+                "lookups": {
+                    "blocked": {"entries": {"4111": "stolen"}, "default": "ok"},
+                    "limits": {"entries": {"gold": 5000}, "strict": true}
+                },
+                "root": [
+                    {"if": {"in_table": ["blocked", {"var": "card"}]}, "then": {"set": ["blocked", true]}},
+                    {"set": ["status", {"lookup": ["blocked", {"var": "card"}]}]},
+                    {"set": ["limit", {"lookup": ["limits", {"var": "tier"}], "default": 100}]},
+                    {"set": ["strict_limit", {"lookup": ["limits", {"var": "tier"}]}]}
+                ]
+            }"#,
+        )]);
+        let run = |input| test_run(&rt, "cards.rule.json", input).unwrap();
+
+        let known = run(json!({"card": "4111", "tier": "gold"}));
+        assert_eq!(known["output"]["blocked"], json!(true));
+        assert_eq!(known["output"]["strict_limit"], json!(5000));
+        assert!(!known.contains_key("warnings"));
+
+        let unknown = run(json!({"card": "5500", "tier": "tin"}));
+        assert!(unknown["output"].get("blocked").is_none());
+        assert_eq!(unknown["output"]["status"], json!("ok"));
+        assert_eq!(unknown["output"]["limit"], json!(100));
+        // strict tables fail the command rather than answer with a default
+        assert!(unknown["output"].get("strict_limit").is_none());
+        assert_eq!(
+            unknown["warnings"],
+            json!([
+                "lookup of 5500 missed table blocked",
+                "cards.rule.json: Value `No key tin in table limits` did not exist"
+            ])
+        );
+    }
+}
@@ -1,10 +1,16 @@
+use crate::rules::lookups::LookupTable;
+use crate::runtime::LookupCache;
 use crate::PicoValue;
 
 use serde::Serialize;
 use serde_json::json;
 use std::boxed::Box;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::mem::replace;
+use std::sync::Arc;
+
+/// separates namespace and key when reading a namespaced value, `{"var": "ns::key"}`
+pub const NAMESPACE_SEPARATOR: &str = "::";
 
 #[derive(Debug, Clone, Serialize)]
 pub enum StateValue {
@@ -27,6 +33,27 @@ pub struct PicoContext {
 
     /// The input JSON value, typically an Object
     pub input_json: Option<serde_json::Value>,
+
+    /// namespaces each running rule file can access, the innermost include last
+    #[serde(skip)]
+    namespace_access: Vec<HashSet<Namespace>>,
+
+    /// problems that did not stop the rules, such as lookup misses
+    #[serde(skip)]
+    warnings: Vec<String>,
+
+    /// why the rules stopped early, set by a `stop` command
+    #[serde(skip)]
+    stopped: Option<String>,
+
+    /// globals and lookup tables replaced for this context only, such as by a test case
+    #[serde(skip)]
+    global_overrides: VariablesMap,
+    #[serde(skip)]
+    table_overrides: HashMap<String, Arc<LookupTable>>,
+    // the external tables of the rule set version being run
+    #[serde(skip)]
+    lookups: Option<Arc<LookupCache>>,
 }
 
 impl Default for PicoContext {
@@ -35,6 +62,12 @@ impl Default for PicoContext {
             namespaced_variables: HashMap::new(),
             local_variables: Box::new(HashMap::new()),
             input_json: None,
+            namespace_access: Vec::new(),
+            warnings: Vec::new(),
+            stopped: None,
+            global_overrides: HashMap::new(),
+            table_overrides: HashMap::new(),
+            lookups: None,
         }
     }
 }
@@ -77,6 +110,24 @@ impl PicoContext {
             .and_then(|hm| hm.insert(key.to_string(), value.clone()));
     }
 
+    /// a rule file starts running with access to `allowed` namespaces
+    pub fn ns_enter(&mut self, allowed: HashSet<Namespace>) {
+        self.namespace_access.push(allowed);
+    }
+
+    /// the running rule file finished, access reverts to the file that included it
+    pub fn ns_leave(&mut self) {
+        self.namespace_access.pop();
+    }
+
+    /// true when the running rule file may read and write namespace `ns`
+    pub fn ns_allowed(&self, ns: &str) -> bool {
+        self.namespace_access
+            .last()
+            .map(|allowed| allowed.contains(ns))
+            .unwrap_or(false)
+    }
+
     pub fn local_set(&mut self, key: &str, value: &PicoValue) {
         self.local_variables.insert(key.to_string(), value.clone());
     }
@@ -94,7 +145,64 @@ impl PicoContext {
         *vm
     }
 
+    /// records a problem that did not stop the rules, returned with the final context
+    pub fn warn(&mut self, message: String) {
+        warn!("{}", message);
+        self.warnings.push(message);
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// stops the rules, the first reason given is kept
+    pub fn stop(&mut self, reason: Option<String>) {
+        if self.stopped.is_none() {
+            info!("stopped: {:?}", reason);
+            self.stopped = Some(reason.unwrap_or_default());
+        }
+    }
+
+    pub fn stopped(&self) -> Option<&str> {
+        self.stopped.as_deref()
+    }
+
+    /// rules running with this context read `value` for global `key` instead of the runtime's
+    pub fn override_global(&mut self, key: &str, value: PicoValue) {
+        self.global_overrides.insert(key.to_string(), value);
+    }
+
+    pub fn global_override(&self, key: &str) -> Option<&PicoValue> {
+        self.global_overrides.get(key)
+    }
+
+    /// rules running with this context use `table` wherever they name `table_name`
+    pub fn override_table(&mut self, table_name: &str, table: Arc<LookupTable>) {
+        self.table_overrides.insert(table_name.to_string(), table);
+    }
+
+    pub fn table_override(&self, table_name: &str) -> Option<Arc<LookupTable>> {
+        self.table_overrides.get(table_name).cloned()
+    }
+
+    /// external lookups resolve against `lookups` rather than the runtime's current version
+    pub fn set_lookups(&mut self, lookups: Arc<LookupCache>) {
+        self.lookups = Some(lookups);
+    }
+
+    pub fn lookups(&self) -> Option<&Arc<LookupCache>> {
+        self.lookups.as_ref()
+    }
+
     pub fn get_value(&self, key: &str) -> Option<&PicoValue> {
+        if let Some((ns, ns_key)) = key.split_once(NAMESPACE_SEPARATOR) {
+            if !self.ns_allowed(ns) {
+                warn!("namespace {}, read denied for {}", ns, ns_key);
+                return None;
+            }
+            return self.ns_get(ns, ns_key);
+        }
+
         match self.local_get(key) {
             Some(v) => Some(v),
             None => {
@@ -113,7 +221,13 @@ impl PicoContext {
         let mut variables: VariablesMap = HashMap::new();
         variables.insert("input".to_string(), json!(&self.input_json));
         variables.insert("output".to_string(), json!(&self.local_variables));
-        //variables.insert("namespaced".to_string(), json!(&self.namespaced_variables));
+        variables.insert("namespaced".to_string(), json!(&self.namespaced_variables));
+        if !self.warnings.is_empty() {
+            variables.insert("warnings".to_string(), json!(&self.warnings));
+        }
+        if let Some(reason) = &self.stopped {
+            variables.insert("stop".to_string(), json!(reason));
+        }
 
         variables
     }
@@ -1,6 +1,7 @@
 //use std::fmt;
 use thiserror::Error;
 
+use crate::rules::schemas::SchemaViolation;
 use crate::values::PicoValue;
 
 #[derive(Error, Debug)]
@@ -31,12 +32,70 @@ pub enum PicoError {
 
     #[error("Fatal failure")]
     Crash(String),
+
+    #[error("Global [{key:?}] is read only")]
+    ReadOnlyGlobal { key: String },
+
+    #[error(
+        "Lookup table [{table:?}] is not accessible, it needs one of the namespaces {namespaces:?}"
+    )]
+    TableDenied {
+        table: String,
+        namespaces: Vec<String>,
+    },
+
+    #[error("Namespace [{namespace:?}] is not accessible")]
+    NamespaceDenied { namespace: String },
+
+    #[error(transparent)]
+    State(#[from] StateError),
+
+    #[error("Match pattern [{pattern:?}] is invalid: {reason}")]
+    InvalidPattern { pattern: String, reason: String },
+
+    #[error("Match pattern [{pattern:?}] is not a literal and dynamic patterns are forbidden")]
+    DynamicPatternDenied { pattern: String },
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("State [{namespace}::{key}] is not an integer")]
+    NotANumber { namespace: String, key: String },
+
+    #[error("State [{namespace}::{key}] would overflow")]
+    Overflow { namespace: String, key: String },
+
+    #[error("State store failure: {0}")]
+    Backend(String),
 }
 
 #[derive(Debug, Error)]
 pub enum RuntimeError {
     #[error("No Such rule: [{rulename:?}]")]
     NoSuchRule { rulename: String },
+
+    #[error("No Such rule set version: [{version:?}]")]
+    NoSuchVersion { version: String },
+
+    #[error("Input does not match the input_schema of [{rulename:?}]: {}", join(.violations))]
+    InvalidInput {
+        rulename: String,
+        violations: Vec<SchemaViolation>,
+    },
+
+    #[error("Output does not match the output_schema of [{rulename:?}]: {}", join(.violations))]
+    InvalidOutput {
+        rulename: String,
+        violations: Vec<SchemaViolation>,
+    },
+}
+
+fn join(violations: &[SchemaViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 #[derive(Debug, Error)]
@@ -56,6 +115,35 @@ pub enum RuleFileError {
         filename: String,
     },
 
+    #[error("Failed to parse YAML [{filename:?}]")]
+    YamlParseError {
+        source: serde_yaml::Error,
+        filename: String,
+    },
+
+    #[error("Failed to parse TOML [{filename:?}]")]
+    TomlParseError {
+        source: toml::de::Error,
+        filename: String,
+    },
+
+    #[error("Bad lookup table [{filename:?}] line {line}: {reason}")]
+    TableError {
+        filename: String,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("Failed to parse [{filename:?}] line {line}")]
+    JsonLinesError {
+        source: serde_json::Error,
+        filename: String,
+        line: usize,
+    },
+
+    #[error("Failed to render as {format}: {reason}")]
+    RenderError { format: String, reason: String },
+
     #[error("Recursive include [{filename:?}]")]
     RecursiveInclude { filename: String },
 
@@ -64,6 +152,12 @@ pub enum RuleFileError {
 
     #[error("Unsuported [{url:?}]")]
     Unsuported { url: String },
+
+    #[error("No such document [{filename:?}]")]
+    MissingDocument { filename: String },
+
+    #[error("Rule source [{url:?}] failed: {reason}")]
+    SourceFailure { url: String, reason: String },
 }
 
 /*impl fmt::Display for PicoError {
@@ -16,6 +16,10 @@ pub mod commands;
 pub mod conditions;
 pub mod context;
 pub mod errors;
+pub mod lint;
+pub mod replay;
 pub mod rules;
 pub mod runtime;
+pub mod schema;
+pub mod testing;
 pub mod values;
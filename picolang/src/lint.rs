@@ -0,0 +1,382 @@
+//! Static checks for rule files, run before the rules ever see an input.
+//!
+//! Errors are mistakes that will fail or misbehave at runtime, warnings are code that is
+//! probably not what was meant. Every lint has a JSON pointer into the rule file.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::commands::action::Action;
+use crate::commands::setting::Settable;
+use crate::commands::Command;
+use crate::conditions::Condition;
+use crate::rules::schemas::schema_allows;
+use crate::rules::walk::{walk_rulefile, RuleVisitor};
+use crate::rules::{RuleFile, RuleFileRoot};
+use crate::runtime::{compile_pattern, PicoRuntime, ValidationError};
+use crate::values::{PicoValue, ValueProducer, VarValue};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a rule file, `path` is a JSON pointer into `rule`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: String,
+    pub path: String,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Lint {
+    fn new(
+        rule: &str,
+        path: &str,
+        severity: Severity,
+        code: &'static str,
+        message: String,
+    ) -> Self {
+        Self {
+            rule: rule.to_string(),
+            path: path.to_string(),
+            severity,
+            code,
+            message,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} {} [{}] {}",
+            self.rule, self.path, self.severity, self.code, self.message
+        )
+    }
+}
+
+/// validation errors stop a rule file being installed, so they are all errors
+impl From<ValidationError> for Lint {
+    fn from(error: ValidationError) -> Self {
+        Self {
+            rule: error.rule,
+            path: error.path,
+            severity: Severity::Error,
+            code: error.code,
+            message: error.message,
+        }
+    }
+}
+
+/// Parses a rule file document, linting its regular expressions first.
+///
+/// A bad `regmatch` or `extract` pattern makes the whole document fail to parse,
+/// so they are checked on the JSON before it becomes a [`RuleFile`].
+pub fn parse_rulefile(rulename: &str, document: &PicoValue) -> Result<RuleFile, Vec<Lint>> {
+    let mut lints = Vec::new();
+    for section in &["root", "fini"] {
+        if let Some(commands) = document.get(section) {
+            patterns(rulename, &format!("/{}", section), commands, &mut lints);
+        }
+    }
+    if !lints.is_empty() {
+        return Err(lints);
+    }
+
+    serde_json::from_value(document.clone()).map_err(|e| {
+        vec![Lint::new(
+            rulename,
+            "",
+            Severity::Error,
+            "invalid-rule",
+            e.to_string(),
+        )]
+    })
+}
+
+fn patterns(rulename: &str, path: &str, value: &PicoValue, lints: &mut Vec<Lint>) {
+    match value {
+        PicoValue::Object(map) => {
+            for (key, nested) in map {
+                let key_path = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+                if key == "regmatch" || key == "extract" {
+                    if let Some(PicoValue::String(pattern)) = nested.get(0) {
+                        if let Err(e) = compile_pattern(pattern, false) {
+                            lints.push(Lint::new(
+                                rulename,
+                                &format!("{}/0", key_path),
+                                Severity::Error,
+                                "invalid-regex",
+                                e.to_string(),
+                            ));
+                        }
+                    }
+                }
+                patterns(rulename, &key_path, nested, lints);
+            }
+        }
+        PicoValue::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                patterns(rulename, &format!("{}/{}", path, index), item, lints);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Lints a parsed rule file on its own, [`Linter::lint_in`] adds the checks against a rule set
+#[derive(Default)]
+pub struct Linter {
+    /// a sample input, variables found in it do not need to be set by the rules
+    input: Option<PicoValue>,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// reading a variable that is neither set by the rule nor in the input is only
+    /// reported when the input is known
+    pub fn set_input(mut self, input: PicoValue) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn lint(&self, rulename: &str, rulefile: &RuleFile) -> Vec<Lint> {
+        let mut visitor = LintVisitor {
+            rulename,
+            lints: Vec::new(),
+            set: HashSet::new(),
+            read: Vec::new(),
+        };
+        let root: Vec<Option<&Command>> = rulefile
+            .root
+            .iter()
+            .map(|entry| match entry {
+                RuleFileRoot::Command(command) => Some(command),
+                RuleFileRoot::IncludeFile(_) => None,
+            })
+            .collect();
+        visitor.unreachable("/root", &root);
+        walk_rulefile(rulefile, &mut visitor);
+
+        for (path, name) in &visitor.read {
+            if visitor.set.contains(*name) {
+                continue;
+            }
+            if let Some(schema) = &rulefile.input_schema {
+                if !schema_allows(schema, name) {
+                    visitor.lints.push(Lint::new(
+                        rulename,
+                        path,
+                        Severity::Warning,
+                        "unknown-input",
+                        format!(
+                            "{} is never set and the input_schema does not allow it",
+                            name
+                        ),
+                    ));
+                }
+            }
+            if let Some(input) = &self.input {
+                if input.pointer(&format!("/{}", name)).is_none() {
+                    visitor.lints.push(Lint::new(
+                        rulename,
+                        path,
+                        Severity::Warning,
+                        "unset-variable",
+                        format!("{} is never set and is not in the input", name),
+                    ));
+                }
+            }
+        }
+        visitor.lints
+    }
+
+    /// the validation of uploads, against the runtime's current rules, followed by `lint`
+    pub fn lint_in(&self, runtime: &PicoRuntime, rulename: &str, rulefile: &RuleFile) -> Vec<Lint> {
+        let mut lints: Vec<Lint> = runtime
+            .validate_rule(rulename, rulefile)
+            .into_iter()
+            .map(Lint::from)
+            .collect();
+        lints.extend(self.lint(rulename, rulefile));
+        lints
+    }
+}
+
+struct LintVisitor<'r> {
+    rulename: &'r str,
+    lints: Vec<Lint>,
+    /// local variables the rule sets
+    set: HashSet<String>,
+    /// local variables read without a default
+    read: Vec<(String, &'r str)>,
+}
+
+impl<'r> LintVisitor<'r> {
+    fn lint(&mut self, path: &str, severity: Severity, code: &'static str, message: String) {
+        self.lints
+            .push(Lint::new(self.rulename, path, severity, code, message));
+    }
+
+    // anything after a `stop` in the same list never runs
+    fn unreachable(&mut self, path: &str, commands: &[Option<&Command>]) {
+        let stop = commands
+            .iter()
+            .position(|command| matches!(command, Some(Command::Stop(_))));
+        if let Some(stop) = stop {
+            if stop + 1 < commands.len() {
+                self.lint(
+                    &format!("{}/{}", path, stop + 1),
+                    Severity::Warning,
+                    "unreachable",
+                    format!("never runs, the stop at {}/{} comes first", path, stop),
+                );
+            }
+        }
+    }
+
+    fn unreachable_in(&mut self, path: &str, action: &Action) {
+        if let Action::Commands(commands) = action {
+            let commands: Vec<Option<&Command>> = commands.iter().map(Some).collect();
+            self.unreachable(path, &commands);
+        }
+    }
+}
+
+impl<'r> RuleVisitor<'r> for LintVisitor<'r> {
+    fn visit_command(&mut self, path: &str, command: &'r Command) {
+        match command {
+            Command::Set(set) => match &set.set {
+                Settable::ValueProducing(name, _) => {
+                    self.set.insert(name.clone());
+                }
+                Settable::Extractor(extract) => {
+                    let names = extract.extract.0.capture_names().flatten();
+                    self.set.extend(names.map(String::from));
+                }
+            },
+            Command::IfThenElse(ite) => {
+                self.unreachable_in(&format!("{}/then", path), &ite.r#then);
+                if let Some(else_branch) = &ite.r#else {
+                    self.unreachable_in(&format!("{}/else", path), else_branch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_condition(&mut self, path: &str, condition: &'r Condition) {
+        if let Condition::Match(m) = condition {
+            let pattern_path = format!("{}/match/1", path);
+            match &m.r#match.1 {
+                ValueProducer::LiteralString(pattern) => {
+                    if let Err(e) = compile_pattern(&pattern.0, false) {
+                        self.lint(
+                            &pattern_path,
+                            Severity::Error,
+                            "invalid-regex",
+                            e.to_string(),
+                        );
+                    }
+                }
+                ValueProducer::LiteralI64(_) | ValueProducer::UnsupportedObject(_) => self.lint(
+                    &pattern_path,
+                    Severity::Error,
+                    "match-pattern",
+                    "match patterns must be strings, this one never matches".to_string(),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_value(&mut self, path: &str, value: &'r ValueProducer) {
+        if let ValueProducer::VarLookup(lookup) = value {
+            if let VarValue::Lookup(name) = &lookup.var {
+                // namespaced variables are set by other rule files
+                if !name.contains(crate::context::NAMESPACE_SEPARATOR) {
+                    self.read.push((format!("{}/var", path), name));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rule_files_are_linted() {
+        let bad_regex = json!({"root": [
+            {"if": {"regmatch": ["(unclosed", {"var": "name"}]}, "then": {"stop": "x"}}
+        ]});
+        let lints = parse_rulefile("bad.rule.json", &bad_regex).unwrap_err();
+        assert_eq!(lints[0].code, "invalid-regex");
+        assert_eq!(lints[0].path, "/root/0/if/regmatch/0");
+
+        let rulefile = parse_rulefile(
+            "lint.rule.json",
+            &json!({"root": [
+                {"set": ["greeting", "hello"]},
+                {"if": {"match": [{"var": "greeting"}, 42]}, "then": [
+                    {"stop": "matched"},
+                    {"set": ["after", 1]}
+                ]},
+                {"set": ["copy", {"var": "customer/name"}]},
+                {"set": ["other", {"var": "missing"}]}
+            ]}),
+        )
+        .unwrap();
+        let linter = Linter::new().set_input(json!({"customer": {"name": "n"}}));
+        let lints = linter.lint("lint.rule.json", &rulefile);
+        let found: Vec<(&str, &str)> = lints
+            .iter()
+            .map(|lint| (lint.code, lint.path.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("unreachable", "/root/1/then/1"),
+                ("match-pattern", "/root/1/if/match/1"),
+                ("unset-variable", "/root/3/set/1/var"),
+            ]
+        );
+
+        let typed = parse_rulefile(
+            "typed.rule.json",
+            &json!({
+                "input_schema": {"properties": {"customer": {"properties": {"name": {}}}}},
+                "root": [{"set": ["copy", {"var": "customer/nmae"}]}]
+            }),
+        )
+        .unwrap();
+        let lints = Linter::new().lint("typed.rule.json", &typed);
+        assert_eq!(lints[0].code, "unknown-input");
+        assert_eq!(lints[0].path, "/root/0/set/1/var");
+    }
+}
@@ -0,0 +1,238 @@
+//! Replays a corpus of inputs, one JSON document per line, through a rule and compares what it
+//! made of each input with a golden snapshot or with another version of the rules.
+
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::Write;
+
+use crate::errors::RuleFileError;
+use crate::runtime::{PicoRuntime, RuleSetVersion};
+use crate::values::PicoValue;
+
+/// the parts of the final context that are recorded, the input is kept alongside them
+const RECORDED: [&str; 3] = ["output", "namespaced", "stop"];
+
+/// What a rule made of one input, a snapshot file holds one per line in corpus order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub input: PicoValue,
+    /// the recorded parts of the final context, or `{"error": ...}`
+    pub result: PicoValue,
+}
+
+/// a value that differs between two outcomes, `path` is a JSON pointer into the outcome
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub before: Option<PicoValue>,
+    pub after: Option<PicoValue>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChangedInput {
+    /// which input of the corpus, from 1, blank lines are not counted
+    pub number: usize,
+    pub input: PicoValue,
+    pub decision_changed: bool,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayReport {
+    pub inputs: usize,
+    pub changed: Vec<ChangedInput>,
+}
+
+impl ReplayReport {
+    pub fn decisions_changed(&self) -> usize {
+        self.changed
+            .iter()
+            .filter(|changed| changed.decision_changed)
+            .count()
+    }
+}
+
+fn read_lines<T: serde::de::DeserializeOwned>(filename: &str) -> Result<Vec<T>, RuleFileError> {
+    let text = fs::read_to_string(filename).map_err(|source| RuleFileError::ReadError {
+        source,
+        filename: filename.to_string(),
+    })?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            serde_json::from_str(line).map_err(|source| RuleFileError::JsonLinesError {
+                source,
+                filename: filename.to_string(),
+                line: n + 1,
+            })
+        })
+        .collect()
+}
+
+/// the inputs of a JSON Lines corpus, blank lines are skipped
+pub fn read_corpus(filename: &str) -> Result<Vec<PicoValue>, RuleFileError> {
+    read_lines(filename)
+}
+
+pub fn read_snapshot(filename: &str) -> Result<Vec<Outcome>, RuleFileError> {
+    read_lines(filename)
+}
+
+pub fn write_snapshot(filename: &str, outcomes: &[Outcome]) -> Result<(), RuleFileError> {
+    let mut file = fs::File::create(filename)?;
+    for outcome in outcomes {
+        let line = serde_json::to_string(outcome).map_err(|source| RuleFileError::ParseError {
+            source,
+            filename: filename.to_string(),
+        })?;
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// runs every input through `rulename` with the runtime's current rules
+pub fn record(runtime: &PicoRuntime, rulename: &str, inputs: Vec<PicoValue>) -> Vec<Outcome> {
+    record_in(runtime, &runtime.current(), rulename, inputs)
+}
+
+/// runs every input through `rulename` as it is in `version`
+pub fn record_in(
+    runtime: &PicoRuntime,
+    version: &RuleSetVersion,
+    rulename: &str,
+    inputs: Vec<PicoValue>,
+) -> Vec<Outcome> {
+    let results = runtime.exec_batch_in(version, rulename, inputs.clone());
+    inputs
+        .into_iter()
+        .zip(results)
+        .map(|(input, result)| {
+            let result = match result {
+                Ok(final_ctx) => recorded(&final_ctx),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            Outcome { input, result }
+        })
+        .collect()
+}
+
+fn recorded(final_ctx: &HashMap<String, PicoValue>) -> PicoValue {
+    let mut recorded = serde_json::Map::new();
+    for key in RECORDED.iter() {
+        if let Some(value) = final_ctx.get(*key) {
+            recorded.insert(key.to_string(), value.clone());
+        }
+    }
+    PicoValue::Object(recorded)
+}
+
+/// Compares outcomes line by line.
+///
+/// `decision` is a JSON pointer into the result, such as `/output/approved`, an input changed
+/// decision when the value there differs, any change counts when it is not given.
+pub fn compare(before: &[Outcome], after: &[Outcome], decision: Option<&str>) -> ReplayReport {
+    let missing = Outcome {
+        input: PicoValue::Null,
+        result: PicoValue::Null,
+    };
+    let inputs = before.len().max(after.len());
+    let changed = (0..inputs)
+        .filter_map(|n| {
+            let old = before.get(n).unwrap_or(&missing);
+            let new = after.get(n).unwrap_or(&missing);
+            let mut changes = Vec::new();
+            diff("/input", Some(&old.input), Some(&new.input), &mut changes);
+            diff(
+                "/result",
+                Some(&old.result),
+                Some(&new.result),
+                &mut changes,
+            );
+            if changes.is_empty() {
+                return None;
+            }
+            let decision_changed = match decision {
+                Some(pointer) => old.result.pointer(pointer) != new.result.pointer(pointer),
+                None => true,
+            };
+            Some(ChangedInput {
+                number: n + 1,
+                input: new.input.clone(),
+                decision_changed,
+                changes,
+            })
+        })
+        .collect();
+
+    ReplayReport { inputs, changed }
+}
+
+fn diff(
+    path: &str,
+    before: Option<&PicoValue>,
+    after: Option<&PicoValue>,
+    changes: &mut Vec<Change>,
+) {
+    match (before, after) {
+        (Some(PicoValue::Object(before)), Some(PicoValue::Object(after))) => {
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let key_path = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+                diff(&key_path, before.get(key), after.get(key), changes);
+            }
+        }
+        (before, after) if before == after => {}
+        (before, after) => changes.push(Change {
+            path: path.to_string(),
+            before: before.cloned(),
+            after: after.cloned(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+    use crate::rules::sources::MemorySource;
+
+    #[test]
+    fn changed_decisions_are_reported() {
+        let source = MemorySource::new().with_document(
+            "limit.rule.json",
+            r#"{"root": [{"if": {"lt": [{"var": "amount"}, 100]}, "then": {"set": ["approved", true]}}]}"#,
+        );
+        let rt = PicoRuntime::new().set_rule_source(source).initialise();
+        let corpus = vec![json!({"amount": 50}), json!({"amount": 150})];
+        let golden = record(&rt, "limit.rule.json", corpus.clone());
+
+        let raised = StringLoader::new(
+            "limit.rule.json",
+            r#"{"root": [{"if": {"lt": [{"var": "amount"}, 200]}, "then": {"set": ["approved", true]}}]}"#,
+        )
+        .load()
+        .unwrap();
+        let previous = rt.current_version();
+        rt.post_rule("limit.rule.json", raised);
+        let replayed = record(&rt, "limit.rule.json", corpus.clone());
+
+        let report = compare(&golden, &replayed, Some("/output/approved"));
+        assert_eq!(report.inputs, 2);
+        assert_eq!(report.decisions_changed(), 1);
+        assert_eq!(report.changed[0].number, 2);
+        assert_eq!(
+            report.changed[0].changes,
+            vec![Change {
+                path: "/result/output/approved".to_string(),
+                before: None,
+                after: Some(json!(true)),
+            }]
+        );
+
+        let earlier = rt.version(&previous).unwrap();
+        let again = record_in(&rt, &earlier, "limit.rule.json", corpus);
+        assert!(compare(&golden, &again, None).changed.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Work done once when a rule file is installed rather than on every request.
+//!
+//! * `match` patterns that are literal strings are compiled to a `Regex`
+//! * an `if` whose condition only compares literals is evaluated, `then` or `else` runs without it
+//! * `debug` templates are checked, so a broken one is reported when it is installed
+//!
+//! The results are kept alongside the nodes they were made from, the rules still run as the
+//! tree they were parsed into, and render and hash exactly as they were written.
+//!
+//! There is no separate execution plan: variables are still looked up by name, includes still
+//! run through the rule set, and a `debug` template is parsed again each time it is rendered,
+//! a `TinyTemplate` borrows its text and can not be shared between threads, so it is not kept.
+//! `bench/compare.sh` measures what this does.
+
+use std::sync::OnceLock;
+use tinytemplate::TinyTemplate;
+
+use crate::commands::execution::ConditionExecution;
+use crate::commands::Command;
+use crate::conditions::Condition;
+use crate::context::PicoContext;
+use crate::rules::walk::{walk_rulefile, RuleVisitor};
+use crate::rules::{PicoRules, RuleFile};
+use crate::runtime::{compile_pattern, PicoRuntime};
+use crate::values::ValueProducer;
+
+/// what was prepared for a rule file, and what will fail when it runs
+#[derive(Debug, Default, PartialEq)]
+pub struct Compiled {
+    pub patterns: usize,
+    pub folded: usize,
+    pub templates: usize,
+    pub failures: Vec<String>,
+}
+
+pub fn compile(rulename: &str, rulefile: &RuleFile) -> Compiled {
+    let mut compiler = Compiler {
+        rulename,
+        compiled: Compiled::default(),
+    };
+    walk_rulefile(rulefile, &mut compiler);
+    compiler.compiled
+}
+
+struct Compiler<'a> {
+    rulename: &'a str,
+    compiled: Compiled,
+}
+
+// literal conditions never touch the runtime, one empty runtime serves every rule file
+fn constants_runtime() -> &'static PicoRuntime {
+    static RUNTIME: OnceLock<PicoRuntime> = OnceLock::new();
+    RUNTIME.get_or_init(PicoRuntime::new)
+}
+
+impl<'a> Compiler<'a> {
+    /// evaluates a literal condition with an empty context, conditions that fail are left to fail
+    /// at runtime
+    fn fold(&self, condition: &Condition) -> Option<bool> {
+        if !is_constant(condition) {
+            return None;
+        }
+        let mut ctx = PicoContext::new();
+        condition
+            .run_with_context(
+                &PicoRules::new(self.rulename),
+                constants_runtime(),
+                &mut ctx,
+            )
+            .ok()
+    }
+}
+
+impl<'r, 'a> RuleVisitor<'r> for Compiler<'a> {
+    fn visit_command(&mut self, path: &str, command: &'r Command) {
+        match command {
+            Command::IfThenElse(ite) => {
+                if let Some(result) = self.fold(&ite.r#if) {
+                    let _ = ite.folded.set(result);
+                    self.compiled.folded += 1;
+                }
+            }
+            Command::DebugLog(debug_log) => {
+                let mut tt = TinyTemplate::new();
+                match tt.add_template("debug", debug_log.template()) {
+                    Ok(_) => self.compiled.templates += 1,
+                    Err(e) => {
+                        warn!("{} debug template will fail: {}", self.rulename, e);
+                        self.compiled
+                            .failures
+                            .push(format!("{}: debug template {}", path, e));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_condition(&mut self, path: &str, condition: &'r Condition) {
+        if let Condition::Match(m) = condition {
+            if let ValueProducer::LiteralString(pattern) = &m.r#match.1 {
+                match compile_pattern(&pattern.0, false) {
+                    Ok(regex) => {
+                        let _ = m.pattern.set(regex);
+                        self.compiled.patterns += 1;
+                    }
+                    Err(e) => {
+                        warn!("{} match pattern will fail: {}", self.rulename, e);
+                        self.compiled
+                            .failures
+                            .push(format!("{}/match/1: {}", path, e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_literal(value: &ValueProducer) -> bool {
+    matches!(
+        value,
+        ValueProducer::LiteralString(_) | ValueProducer::LiteralI64(_)
+    )
+}
+
+/// conditions that give the same result whatever the input, state or globals
+fn is_constant(condition: &Condition) -> bool {
+    let pair = |(lhs, rhs): &(ValueProducer, ValueProducer)| is_literal(lhs) && is_literal(rhs);
+    match condition {
+        Condition::Eq(c) => pair(&c.eq),
+        Condition::Match(c) => match &c.r#match {
+            (lhs, ValueProducer::LiteralString(pattern)) => {
+                is_literal(lhs) && compile_pattern(&pattern.0, false).is_ok()
+            }
+            _ => false,
+        },
+        Condition::StartsWith(c) => pair(&c.match_start),
+        Condition::GreaterThan(c) => pair(&c.gt),
+        Condition::LessThan(c) => pair(&c.lt),
+        Condition::RegMatch(c) => is_literal(&c.regmatch.1),
+        Condition::And(c) => c.and.iter().all(is_constant),
+        Condition::Or(c) => c.or.iter().all(is_constant),
+        Condition::Not(c) => is_constant(&c.not),
+        Condition::VarExists(_)
+        | Condition::VarMissing(_)
+        | Condition::Rate(_)
+        | Condition::InTable(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+
+    #[test]
+    fn literals_are_prepared_once() {
+        let rulefile = StringLoader::new(
+            "compiled.rule.json",
+            r#"{"root": [
+                {"if": {"match": ["ban", "bannana"]}, "then": {"set": ["folded", true]}},
+                {"if": {"and": [{"eq": [1, 1]}, {"match": [{"var": "name"}, "^gl"]}]},
+                 "then": {"debug": "name is {name}"}},
+                {"if": {"not": {"lt": [2, 1]}}, "then": {"set": ["also", true]}},
+                {"debug": "unclosed {name"}
+            ]}"#,
+        )
+        .load()
+        .unwrap();
+
+        let compiled = compile("compiled.rule.json", &rulefile);
+        assert_eq!(
+            (compiled.patterns, compiled.folded, compiled.templates),
+            (2, 2, 1)
+        );
+        assert_eq!(compiled.failures.len(), 1);
+        assert!(compiled.failures[0].starts_with("/root/3: debug template"));
+        // rendering is unchanged
+        let rendered = serde_json::to_value(&rulefile).unwrap();
+        assert_eq!(
+            rendered["root"][0]["if"],
+            serde_json::json!({"match": ["ban", "bannana"]})
+        );
+    }
+}
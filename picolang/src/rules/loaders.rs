@@ -1,10 +1,149 @@
 use crate::commands::execution::AnyResult;
 use crate::errors::RuleFileError;
 use crate::rules::RuleFile;
-use std::fs::File;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
 
 pub type LoaderResult = AnyResult<RuleFile, RuleFileError>;
 
+/// Document formats a rule or lookup file can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RuleFormat {
+  #[default]
+  Json,
+  Yaml,
+  Toml,
+}
+
+impl fmt::Display for RuleFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RuleFormat::Json => write!(f, "json"),
+      RuleFormat::Yaml => write!(f, "yaml"),
+      RuleFormat::Toml => write!(f, "toml"),
+    }
+  }
+}
+
+impl RuleFormat {
+  const ALL: [RuleFormat; 3] = [RuleFormat::Json, RuleFormat::Yaml, RuleFormat::Toml];
+
+  pub fn extensions(&self) -> &'static [&'static str] {
+    match self {
+      RuleFormat::Json => &["json"],
+      RuleFormat::Yaml => &["yaml", "yml"],
+      RuleFormat::Toml => &["toml"],
+    }
+  }
+
+  pub fn mime_type(&self) -> &'static str {
+    match self {
+      RuleFormat::Json => "application/json",
+      RuleFormat::Yaml => "application/x-yaml",
+      RuleFormat::Toml => "application/toml",
+    }
+  }
+
+  /// picks the format from the filename extension
+  pub fn from_filename(filename: &str) -> Option<Self> {
+    let extension = filename.rsplit('.').next()?;
+    Self::ALL
+      .iter()
+      .find(|format| format.extensions().contains(&extension))
+      .copied()
+  }
+
+  /// true when the filename looks like `name.<kind>.<ext>` for any supported extension,
+  /// so `is_kind("a.rule.yaml", "rule")` holds
+  pub fn is_kind(filename: &str, kind: &str) -> bool {
+    match (Self::from_filename(filename), filename.rfind('.')) {
+      (Some(_), Some(dot)) => filename[..dot].ends_with(&format!(".{}", kind)),
+      _ => false,
+    }
+  }
+
+  pub fn from_mime(mime: &str) -> Option<Self> {
+    match mime.trim() {
+      "application/json" | "text/json" => Some(RuleFormat::Json),
+      "application/x-yaml" | "application/yaml" | "text/yaml" | "text/x-yaml" => {
+        Some(RuleFormat::Yaml)
+      }
+      "application/toml" | "text/toml" | "text/x-toml" => Some(RuleFormat::Toml),
+      _ => None,
+    }
+  }
+
+  /// picks the first acceptable format from an HTTP `Accept` header value,
+  /// wildcards and an empty header pick JSON
+  pub fn from_accept(accept: &str) -> Option<Self> {
+    if accept.trim().is_empty() {
+      return Some(RuleFormat::Json);
+    }
+    accept
+      .split(',')
+      .map(|media_range| media_range.split(';').next().unwrap_or_default().trim())
+      .find_map(|mime| match mime {
+        "*/*" | "application/*" => Some(RuleFormat::Json),
+        _ => Self::from_mime(mime),
+      })
+  }
+
+  pub fn parse<T: DeserializeOwned>(
+    &self,
+    filename: &str,
+    document: &str,
+  ) -> Result<T, RuleFileError> {
+    match self {
+      RuleFormat::Json => {
+        serde_json::from_str(document).map_err(|source| RuleFileError::ParseError {
+          source,
+          filename: filename.to_string(),
+        })
+      }
+      RuleFormat::Yaml => {
+        serde_yaml::from_str(document).map_err(|source| RuleFileError::YamlParseError {
+          source,
+          filename: filename.to_string(),
+        })
+      }
+      RuleFormat::Toml => {
+        toml::from_str(document).map_err(|source| RuleFileError::TomlParseError {
+          source,
+          filename: filename.to_string(),
+        })
+      }
+    }
+  }
+
+  pub fn render<T: Serialize>(&self, value: &T) -> Result<String, RuleFileError> {
+    let rendered = match self {
+      RuleFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+      RuleFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+      // going via toml::Value lets toml order plain values ahead of tables
+      RuleFormat::Toml => toml::Value::try_from(value)
+        .and_then(|v| toml::to_string_pretty(&v))
+        .map_err(|e| e.to_string()),
+    };
+
+    rendered.map_err(|reason| RuleFileError::RenderError {
+      format: self.to_string(),
+      reason,
+    })
+  }
+}
+
+/// reads and parses a file, choosing the format from its extension
+pub fn read_document<T: DeserializeOwned>(filename: &str) -> Result<T, RuleFileError> {
+  let format = RuleFormat::from_filename(filename).unwrap_or_default();
+  let document = fs::read_to_string(filename).map_err(|source| RuleFileError::ReadError {
+    source,
+    filename: filename.to_string(),
+  })?;
+  format.parse(filename, &document)
+}
+
 pub trait PicoRuleLoader {
   fn load(&self) -> LoaderResult;
   fn filename_is(&self) -> String;
@@ -16,35 +155,54 @@ pub trait PicoRuleLoader {
 pub struct StringLoader {
   filename: String,
   document: String,
+  format: Option<RuleFormat>,
 }
 impl StringLoader {
   pub fn new(filename: &str, document: &str) -> Self {
     Self {
       filename: filename.to_string(),
       document: document.to_string(),
+      format: None,
     }
   }
+
+  /// parse the document as `format` rather than guessing from the filename
+  pub fn with_format(mut self, format: RuleFormat) -> Self {
+    self.format = Some(format);
+    self
+  }
 }
 impl PicoRuleLoader for StringLoader {
   fn filename_is(&self) -> String {
     self.filename.to_string()
   }
   fn load(&self) -> LoaderResult {
-    let rule_file: RuleFile = serde_json::from_str(&self.document).expect("a JSON document");
-    Ok(rule_file)
+    let format = self
+      .format
+      .or_else(|| RuleFormat::from_filename(&self.filename))
+      .unwrap_or_default();
+    format.parse(&self.filename, &self.document)
   }
 }
 
 pub struct FileLoader {
   filename: String,
+  format: Option<RuleFormat>,
 }
 impl FileLoader {
   pub fn new(filename: &str) -> Self {
     warn!("creating a new FileLoader");
     Self {
       filename: filename.to_string(),
+      format: None,
     }
   }
+
+  /// parse the file as `format` rather than guessing from the extension
+  pub fn with_format(mut self, format: RuleFormat) -> Self {
+    self.format = Some(format);
+    self
+  }
 }
 
 impl PicoRuleLoader for FileLoader {
@@ -56,11 +214,14 @@ impl PicoRuleLoader for FileLoader {
 
   fn load(&self) -> LoaderResult {
     info!("Loading... {}", self.filename);
-    match File::open(&self.filename) {
-      Ok(opened_file) => {
-        info!("serde_json::from_reader...");
-        let rule_file: RuleFile = serde_json::from_reader(opened_file).unwrap();
-        Ok(rule_file)
+    match fs::read_to_string(&self.filename) {
+      Ok(document) => {
+        let format = self
+          .format
+          .or_else(|| RuleFormat::from_filename(&self.filename))
+          .unwrap_or_default();
+        info!("parsing as {}...", format);
+        format.parse(&self.filename, &document)
       }
       Err(x) => {
         error!("failed to open: {:?}", x);
@@ -72,3 +233,38 @@ impl PicoRuleLoader for FileLoader {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn yaml_and_toml_rules_match_json() {
+    let json = StringLoader::new("a.rule.json", r#"{"root": [{"log": "hi"}]}"#)
+      .load()
+      .unwrap();
+    let yaml = StringLoader::new("a.rule.yaml", "root:\n  - log: hi\n")
+      .load()
+      .unwrap();
+    let toml = StringLoader::new("a.rule", "[[root]]\nlog = \"hi\"\n")
+      .with_format(RuleFormat::Toml)
+      .load()
+      .unwrap();
+
+    let expected = serde_json::to_value(&json).unwrap();
+    assert_eq!(serde_json::to_value(&yaml).unwrap(), expected);
+    assert_eq!(serde_json::to_value(&toml).unwrap(), expected);
+  }
+
+  #[test]
+  fn format_from_filename_and_accept() {
+    assert!(RuleFormat::is_kind("pico.rule.yml", "rule"));
+    assert!(RuleFormat::is_kind("file.lookup.toml", "lookup"));
+    assert!(!RuleFormat::is_kind("pico.rule.txt", "rule"));
+    assert_eq!(
+      RuleFormat::from_accept("text/html, application/x-yaml;q=0.9"),
+      Some(RuleFormat::Yaml)
+    );
+    assert_eq!(RuleFormat::from_accept("text/html"), None);
+  }
+}
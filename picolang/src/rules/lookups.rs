@@ -1,27 +1,80 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
 //use std::rc::Rc;
-use std::fs::File;
 
+use crate::context::PicoContext;
+use crate::errors::RuleFileError;
+use crate::rules::loaders::RuleFormat;
+use crate::rules::matching::TableIndex;
+use crate::rules::sources::{read_source_document, RuleSource};
+use crate::rules::StringOrSeq;
 use crate::PicoValue;
 
 pub type LookupDict = HashMap<String, PicoValue>;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// joins the parts of a composite key, `{"GB|loan": 4.5}` is the entry for `["GB", "loan"]`
+pub const COMPOSITE_KEY_SEPARATOR: &str = "|";
+
+/// a value as (part of) a lookup key, strings are used as they are
+pub fn key_part(value: &PicoValue) -> String {
+    match value {
+        PicoValue::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// How a lookup key is matched against the keys of a table's entries
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    /// entry keys are numeric ranges `lo..hi`, including lo and excluding hi, either can be left out
+    Range,
+    /// the longest entry key the lookup key starts with
+    Prefix,
+    /// entry keys are IP networks `10.0.0.0/8`, the longest network holding the address wins
+    Cidr,
+    /// entry keys are regular expressions matching the whole key, the longest pattern wins
+    Regex,
+    /// entry keys are globs with `*`, `?` and `[...]`, the longest pattern wins
+    Glob,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct LookupTable {
     pub entries: LookupDict,
+    /// value of keys that are not in the table
+    #[serde(default = "LookupTable::unknown")]
     pub default: PicoValue,
+    /// keys that are not in the table are an error rather than the default
+    #[serde(default)]
+    pub strict: bool,
 
-    // namespaces this lookup table is available in
+    /// namespaces this lookup table is available in, only rule files that can access
+    /// one of them can use it, every rule file when left out
     pub namespaces: Option<Vec<String>>,
+
+    #[serde(default, rename = "match")]
+    pub matching: MatchMode,
+
+    // built on first use
+    #[serde(skip)]
+    index: OnceLock<Result<TableIndex, String>>,
 }
 
 impl Default for LookupTable {
     fn default() -> Self {
         Self {
-            default: PicoValue::String("unknown".to_string()),
+            default: LookupTable::unknown(),
+            strict: false,
             entries: HashMap::new(),
             namespaces: None,
+            matching: MatchMode::default(),
+            index: OnceLock::new(),
         }
     }
 }
@@ -31,11 +84,60 @@ impl LookupTable {
         Default::default()
     }
 
+    fn unknown() -> PicoValue {
+        PicoValue::String("unknown".to_string())
+    }
+
+    fn index(&self) -> &Result<TableIndex, String> {
+        self.index
+            .get_or_init(|| TableIndex::build(self.matching, &self.entries))
+    }
+
+    /// builds the index, failing when an entry key does not fit the match mode
+    pub fn prepare(&self) -> Result<(), String> {
+        self.index().as_ref().map(|_| ()).map_err(|e| e.clone())
+    }
+
+    pub fn visible_to(&self, ctx: &PicoContext) -> bool {
+        match &self.namespaces {
+            None => true,
+            Some(namespaces) => namespaces.iter().any(|ns| ctx.ns_allowed(ns)),
+        }
+    }
+
+    /// the entry `key` matches, None when the key is not in the table
+    pub fn get(&self, key: &str) -> Option<&PicoValue> {
+        let found = match self.index() {
+            Ok(index) => index.find(&self.entries, key),
+            Err(e) => {
+                warn!("lookup table can not be used: {}", e);
+                None
+            }
+        };
+        found.and_then(|entry_key| self.entries.get(entry_key))
+    }
+
+    /// the entry `key` matches or the default
     pub fn lookup(&self, key: &str) -> &PicoValue {
-        if let Some(value) = self.entries.get(key) {
-            value
-        } else {
-            &self.default
+        self.get(key).unwrap_or(&self.default)
+    }
+}
+
+/// A table found by [`PicoRules::resolve_table`](crate::rules::PicoRules::resolve_table),
+/// external tables are shared with the runtime so a reload can not drop one that is in use
+#[derive(Debug)]
+pub enum TableRef<'t> {
+    Internal(&'t LookupTable),
+    External(Arc<LookupTable>),
+}
+
+impl Deref for TableRef<'_> {
+    type Target = LookupTable;
+
+    fn deref(&self) -> &LookupTable {
+        match self {
+            TableRef::Internal(table) => table,
+            TableRef::External(table) => table,
         }
     }
 }
@@ -43,36 +145,433 @@ impl LookupTable {
 //pub type Lookups = HashMap<String, Rc<LookupTable>>;
 pub type Lookups = HashMap<String, LookupType>;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum LookupType {
     ExternalTable(String),
     InternalTable(LookupTable),
+    /// an external file read with options, `{"file": "countries.lookup.csv", "key": "iso2"}`
+    ExternalSpec(ExternalTableSpec),
+}
+
+impl LookupType {
+    /// how an external table is read, None for tables written in the rule file
+    pub fn external_spec(&self) -> Option<ExternalTableSpec> {
+        match self {
+            LookupType::ExternalTable(file) => Some(ExternalTableSpec::from(file.as_str())),
+            LookupType::ExternalSpec(spec) => Some(spec.clone()),
+            LookupType::InternalTable(_) => None,
+        }
+    }
+}
+
+/// file extensions of lookup tables with one entry per row or line
+const TABULAR_EXTENSIONS: [&str; 2] = ["csv", "jsonl"];
+
+/// true for `name.lookup.<ext>`, in any rule format or as CSV or JSON Lines
+pub fn is_lookup_file(filename: &str) -> bool {
+    RuleFormat::is_kind(filename, "lookup")
+        || TABULAR_EXTENSIONS
+            .iter()
+            .any(|ext| filename.ends_with(&format!(".lookup.{}", ext)))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    /// the cell holds a JSON document
+    Json,
+}
+
+/// How the rows of a CSV or JSON Lines table become entries
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+pub struct TabularOptions {
+    /// column holding the key, the first CSV column or the `key` field by default,
+    /// several columns make a composite key
+    pub key: Option<StringOrSeq>,
+    /// columns kept in each entry, every column but the key by default
+    pub columns: Option<Vec<String>>,
+    /// declared column types, the type of any other CSV cell is inferred
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub types: HashMap<String, ColumnType>,
+    /// value of keys that are not in the table
+    pub default: Option<PicoValue>,
+    #[serde(default, rename = "match")]
+    pub matching: MatchMode,
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl TabularOptions {
+    fn is_default(&self) -> bool {
+        *self == TabularOptions::default()
+    }
+}
+
+/// An external lookup file and the options it is read with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct ExternalTableSpec {
+    pub file: String,
+    #[serde(flatten)]
+    pub options: TabularOptions,
+}
+
+impl From<&str> for ExternalTableSpec {
+    fn from(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            options: TabularOptions::default(),
+        }
+    }
+}
+
+impl ExternalTableSpec {
+    /// the name the table is cached under, the same file read with other options is another table
+    pub fn cache_name(&self) -> String {
+        if self.options.is_default() {
+            self.file.clone()
+        } else {
+            format!(
+                "{}#{}",
+                self.file,
+                serde_json::to_string(&self.options).unwrap_or_default()
+            )
+        }
+    }
+
+    /// reads the table from `source`, CSV and JSON Lines files use the options
+    pub fn read(&self, source: &dyn RuleSource) -> Result<LookupTable, RuleFileError> {
+        if self.file.ends_with(".csv") {
+            tabular_table(
+                &self.file,
+                csv_rows(&self.file, &source.read(&self.file)?)?,
+                &self.options,
+            )
+        } else if self.file.ends_with(".jsonl") {
+            tabular_table(
+                &self.file,
+                jsonl_rows(&self.file, &source.read(&self.file)?)?,
+                &self.options,
+            )
+        } else {
+            read_source_document::<LookupTable>(source, &self.file)
+        }
+    }
+}
+
+fn table_error(filename: &str, line: usize, reason: impl ToString) -> RuleFileError {
+    RuleFileError::TableError {
+        filename: filename.to_string(),
+        line,
+        reason: reason.to_string(),
+    }
+}
+
+// a row of named cells and the line it came from, a cell is still text when it is
+// from a CSV file
+type Row = (usize, Vec<(String, PicoValue)>);
+
+fn csv_rows(filename: &str, document: &str) -> Result<Vec<Row>, RuleFileError> {
+    let mut reader = csv::Reader::from_reader(document.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| table_error(filename, 1, e))?
+        .clone();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            let line = e.position().map(|p| p.line() as usize).unwrap_or_default();
+            table_error(filename, line, e)
+        })?;
+        let line = record
+            .position()
+            .map(|p| p.line() as usize)
+            .unwrap_or_default();
+        let cells = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, cell)| (header.to_string(), PicoValue::String(cell.to_string())))
+            .collect();
+        rows.push((line, cells));
+    }
+    Ok(rows)
+}
+
+fn jsonl_rows(filename: &str, document: &str) -> Result<Vec<Row>, RuleFileError> {
+    let mut rows = Vec::new();
+    for (index, text) in document.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<PicoValue>(text)
+            .map_err(|e| table_error(filename, index + 1, e))?
+        {
+            PicoValue::Object(object) => rows.push((index + 1, object.into_iter().collect())),
+            _ => return Err(table_error(filename, index + 1, "not a JSON object")),
+        }
+    }
+    Ok(rows)
+}
+
+/// a CSV cell as its declared type, or the narrowest type it parses as
+fn convert_cell(cell: &str, column_type: Option<ColumnType>) -> Result<PicoValue, String> {
+    match column_type {
+        Some(ColumnType::String) => Ok(PicoValue::from(cell)),
+        _ if cell.is_empty() => Ok(PicoValue::Null),
+        Some(ColumnType::Integer) => cell
+            .parse::<i64>()
+            .map(PicoValue::from)
+            .map_err(|e| format!("{} is not an integer: {}", cell, e)),
+        Some(ColumnType::Float) => cell
+            .parse::<f64>()
+            .map(PicoValue::from)
+            .map_err(|e| format!("{} is not a float: {}", cell, e)),
+        Some(ColumnType::Boolean) => cell
+            .parse::<bool>()
+            .map(PicoValue::from)
+            .map_err(|e| format!("{} is not a boolean: {}", cell, e)),
+        Some(ColumnType::Json) => serde_json::from_str(cell).map_err(|e| e.to_string()),
+        None => Ok(if let Ok(i) = cell.parse::<i64>() {
+            PicoValue::from(i)
+        } else if let Ok(f) = cell.parse::<f64>() {
+            PicoValue::from(f)
+        } else if let Ok(b) = cell.parse::<bool>() {
+            PicoValue::from(b)
+        } else {
+            PicoValue::from(cell)
+        }),
+    }
+}
+
+fn tabular_table(
+    filename: &str,
+    rows: Vec<Row>,
+    options: &TabularOptions,
+) -> Result<LookupTable, RuleFileError> {
+    let is_csv = filename.ends_with(".csv");
+    let mut table = LookupTable::new();
+    table.matching = options.matching;
+    table.strict = options.strict;
+    if let Some(default) = &options.default {
+        table.default = default.clone();
+    }
+    table.entries.reserve(rows.len());
+
+    for (line, cells) in rows {
+        let key_columns: Vec<&str> = match &options.key {
+            Some(StringOrSeq(columns)) => columns.iter().map(|c| c.as_str()).collect(),
+            None if is_csv => cells.first().map(|(h, _)| h.as_str()).into_iter().collect(),
+            None => vec!["key"],
+        };
+
+        let mut key_parts: Vec<Option<String>> = vec![None; key_columns.len()];
+        let mut entry = serde_json::Map::new();
+        for (column, value) in cells.iter() {
+            // keys are the text as written, `044` is not the number 44
+            if let Some(position) = key_columns.iter().position(|k| k == column) {
+                key_parts[position] = Some(key_part(value));
+                continue;
+            }
+            let value = match value {
+                PicoValue::String(cell) if is_csv || options.types.contains_key(column) => {
+                    convert_cell(cell, options.types.get(column).copied()).map_err(|reason| {
+                        table_error(filename, line, format!("column {}: {}", column, reason))
+                    })?
+                }
+                other => other.clone(),
+            };
+            if options
+                .columns
+                .as_ref()
+                .map(|c| c.contains(column))
+                .unwrap_or(true)
+            {
+                entry.insert(column.clone(), value);
+            }
+        }
+
+        let key: Option<Vec<String>> = key_parts.into_iter().collect();
+        match key {
+            Some(key) => {
+                let key = key.join(COMPOSITE_KEY_SEPARATOR);
+                if table.entries.contains_key(&key) {
+                    return Err(table_error(
+                        filename,
+                        line,
+                        format!("duplicate key {}", key),
+                    ));
+                }
+                table.entries.insert(key, PicoValue::Object(entry));
+            }
+            None => {
+                return Err(table_error(
+                    filename,
+                    line,
+                    format!("missing key column of {}", key_columns.join(", ")),
+                ))
+            }
+        }
+    }
+    Ok(table)
 }
 
-pub fn get_external_lookup_names(lookups: &Lookups) -> Vec<(&String, &String)> {
-    let c: Vec<(&String, &String)> = lookups
+pub fn get_external_lookups(lookups: &Lookups) -> Vec<(&String, ExternalTableSpec)> {
+    let c: Vec<(&String, ExternalTableSpec)> = lookups
         .iter()
-        .filter_map(|(name, typ)| match typ {
-            LookupType::ExternalTable(t) => Some((name, t)),
-            _ => None,
-        })
+        .filter_map(|(name, typ)| typ.external_spec().map(|spec| (name, spec)))
         .collect();
 
     debug!("ccc1 {:?}", c);
     c
 }
 
-pub fn load_into_cache(filename: &str, cache: &mut HashMap<String, LookupTable>) {
-    let k = filename.to_string();
-
-    match File::open(filename) {
-        Ok(opened_file) => {
-            let lookup_file: LookupTable = serde_json::from_reader(opened_file).unwrap();
-            cache.insert(k, lookup_file);
+pub fn load_into_cache(
+    spec: &ExternalTableSpec,
+    source: &dyn RuleSource,
+    cache: &mut HashMap<String, LookupTable>,
+) {
+    match spec.read(source).and_then(|table| {
+        table
+            .prepare()
+            .map(|_| table)
+            .map_err(|reason| table_error(&spec.file, 0, reason))
+    }) {
+        Ok(lookup_file) => {
+            cache.insert(spec.cache_name(), lookup_file);
         }
         Err(x) => {
-            error!("Failed to open: {:?}", x);
+            error!("Failed to load: {}", x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+    use crate::rules::sources::MemorySource;
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn csv_rows_become_typed_entries() {
+        let source = MemorySource::new().with_document(
+            "countries.lookup.csv",
+            "iso2,name,population,code\nGB,United Kingdom,67000000,044\nFR,France,,033\n",
+        );
+        let spec: ExternalTableSpec = serde_json::from_value(json!({
+            "file": "countries.lookup.csv",
+            "types": {"code": "string"},
+            "default": {}
+        }))
+        .unwrap();
+
+        let table = spec.read(&source).unwrap();
+        assert_eq!(
+            table.lookup("GB"),
+            &json!({"name": "United Kingdom", "population": 67000000, "code": "044"})
+        );
+        assert_eq!(table.lookup("FR")["population"], json!(null));
+        assert_eq!(table.lookup("DE"), &json!({}));
+        assert_ne!(spec.cache_name(), "countries.lookup.csv");
+
+        let rates: ExternalTableSpec = serde_json::from_value(json!({
+            "file": "countries.lookup.csv",
+            "key": ["iso2", "code"],
+            "columns": ["name"],
+            "types": {"code": "string"}
+        }))
+        .unwrap();
+        let table = rates.read(&source).unwrap();
+        assert_eq!(table.lookup("FR|033"), &json!({"name": "France"}));
+
+        // key cells are never converted
+        let by_code = ExternalTableSpec {
+            file: "countries.lookup.csv".to_string(),
+            options: TabularOptions {
+                key: Some(StringOrSeq(vec!["code".to_string()])),
+                ..Default::default()
+            },
+        };
+        let table = by_code.read(&source).unwrap();
+        assert_eq!(table.lookup("044")["name"], json!("United Kingdom"));
+
+        let duplicated = MemorySource::new().with_document(
+            "dup.lookup.csv",
+            "iso2,name
+GB,Britain
+GB,England
+",
+        );
+        match ExternalTableSpec::from("dup.lookup.csv").read(&duplicated) {
+            Err(RuleFileError::TableError { line, reason, .. }) => {
+                assert_eq!((line, reason.as_str()), (3, "duplicate key GB"))
+            }
+            other => panic!("expected a duplicate key error, got {:?}", other),
         }
     }
+
+    #[test]
+    fn lookup_tables_follow_namespace_grants() {
+        let rt = test_runtime(&[
+            (
+                "risk.lookup.json",
+                r#"{"entries": {"GB": "low"}, "namespaces": ["risk"]}"#,
+            ),
+            (
+                "main.rule.json",
+                r#"{
+                    "namespaces": ["risk"],
+                    "lookups": {"risk": "risk.lookup.json"},
+                    "root": [
+                        {"include": "scoring.rule.json", "with_namespaces": "risk"},
+                        {"include": "public.rule.json"},
+                        {"set": ["main", {"lookup": ["risk", {"var": "country"}]}]}
+                    ]
+                }"#,
+            ),
+            (
+                "scoring.rule.json",
+                r#"{
+                    "lookups": {"risk": "risk.lookup.json"},
+                    "root": [{"set": ["score", {"lookup": ["risk", {"var": "country"}]}], "namespaces": ["risk"]}]
+                }"#,
+            ),
+            (
+                "public.rule.json",
+                r#"{
+                    "lookups": {"risk": "risk.lookup.json"},
+                    "root": [{"set": ["leak", {"lookup": ["risk", {"var": "country"}]}]}]
+                }"#,
+            ),
+        ]);
+        let result = test_run(&rt, "main.rule.json", json!({"country": "GB"})).unwrap();
+
+        assert_eq!(result["namespaced"]["risk"]["score"], json!("low"));
+        assert_eq!(result["output"]["main"], json!("low"));
+        assert!(result["output"].get("leak").is_none());
+        let warnings = result["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].as_str().unwrap().contains("public.rule.json"));
+
+        // an upload can not reach the table by declaring a namespace, even one main.rule.json declares
+        let upload = StringLoader::new(
+            "upload.json",
+            r#"{
+                "namespaces": ["risk"],
+                "lookups": {"risk": "risk.lookup.json"},
+                "root": [{"set": ["leak", {"lookup": ["risk", {"var": "country"}]}]}]
+            }"#,
+        )
+        .load()
+        .unwrap();
+        let rejected = rt.upload_rule("evil.rule.json", upload).unwrap_err();
+        assert_eq!(rejected[0].code, "namespace-not-allowed");
+        assert!(!rt.has_rule("evil.rule.json"));
+    }
 }
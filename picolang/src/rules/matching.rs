@@ -0,0 +1,249 @@
+use regex::RegexSet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::rules::lookups::{LookupDict, MatchMode};
+
+// (ipv4, prefix length, entry keys by network address)
+type Networks = (bool, u8, HashMap<u128, String>);
+
+/// An index over a lookup table's entry keys for its match mode,
+/// finds the entry key a lookup key matches
+#[derive(Debug)]
+pub(crate) enum TableIndex {
+    Exact,
+    /// sorted by lower bound
+    Range(Vec<(f64, f64, String)>),
+    /// entry keys by length, longest first
+    Prefix(Vec<usize>),
+    /// networks by family and prefix length, longest first
+    Cidr(Vec<Networks>),
+    /// patterns longest first, the first that matches wins
+    Patterns(RegexSet, Vec<String>),
+}
+
+impl TableIndex {
+    pub(crate) fn build(mode: MatchMode, entries: &LookupDict) -> Result<Self, String> {
+        match mode {
+            MatchMode::Exact => Ok(TableIndex::Exact),
+            MatchMode::Range => {
+                let mut ranges = entries
+                    .keys()
+                    .map(|key| parse_range(key).map(|(lo, hi)| (lo, hi, key.clone())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if let Some((_, _, key)) = ranges.iter().find(|(lo, hi, _)| lo >= hi) {
+                    return Err(format!("range {} is empty", key));
+                }
+                // find only looks at the last range starting before a value
+                if let Some(pair) = ranges.windows(2).find(|pair| pair[0].1 > pair[1].0) {
+                    return Err(format!("ranges {} and {} overlap", pair[0].2, pair[1].2));
+                }
+                Ok(TableIndex::Range(ranges))
+            }
+            MatchMode::Prefix => {
+                let mut lengths: Vec<usize> = entries.keys().map(|key| key.len()).collect();
+                lengths.sort_unstable_by_key(|len| std::cmp::Reverse(*len));
+                lengths.dedup();
+                Ok(TableIndex::Prefix(lengths))
+            }
+            MatchMode::Cidr => {
+                let mut networks: HashMap<(bool, u8), HashMap<u128, String>> = HashMap::new();
+                for key in entries.keys() {
+                    let (v4, bits, prefix) = parse_cidr(key)?;
+                    networks
+                        .entry((v4, prefix))
+                        .or_default()
+                        .insert(mask(bits, prefix, v4), key.clone());
+                }
+                let mut networks: Vec<Networks> = networks
+                    .into_iter()
+                    .map(|((v4, prefix), keys)| (v4, prefix, keys))
+                    .collect();
+                networks.sort_unstable_by_key(|(_, prefix, _)| std::cmp::Reverse(*prefix));
+                Ok(TableIndex::Cidr(networks))
+            }
+            MatchMode::Regex | MatchMode::Glob => {
+                let mut keys: Vec<String> = entries.keys().cloned().collect();
+                keys.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+                let patterns: Vec<String> = match mode {
+                    MatchMode::Glob => keys.iter().map(|key| glob_to_regex(key)).collect(),
+                    _ => keys.iter().map(|key| format!("^(?:{})$", key)).collect(),
+                };
+                let set = RegexSet::new(&patterns).map_err(|e| e.to_string())?;
+                Ok(TableIndex::Patterns(set, keys))
+            }
+        }
+    }
+
+    /// the entry key matching `key`
+    pub(crate) fn find<'t>(&'t self, entries: &'t LookupDict, key: &str) -> Option<&'t str> {
+        match self {
+            TableIndex::Exact => entries.get_key_value(key).map(|(k, _)| k.as_str()),
+            TableIndex::Range(ranges) => {
+                let value: f64 = key.trim().parse().ok()?;
+                // the last range starting at or before value, ranges do not overlap
+                let after = ranges.partition_point(|(lo, _, _)| *lo <= value);
+                ranges[..after]
+                    .last()
+                    .filter(|(_, hi, _)| value < *hi)
+                    .map(|(_, _, k)| k.as_str())
+            }
+            TableIndex::Prefix(lengths) => lengths
+                .iter()
+                .filter(|len| **len <= key.len() && key.is_char_boundary(**len))
+                .find_map(|len| entries.get_key_value(&key[..*len]))
+                .map(|(k, _)| k.as_str()),
+            TableIndex::Cidr(networks) => {
+                let (v4, bits) = parse_ip(key).ok()?;
+                networks
+                    .iter()
+                    .filter(|(family, _, _)| *family == v4)
+                    .find_map(|(_, prefix, keys)| keys.get(&mask(bits, *prefix, v4)))
+                    .map(|k| k.as_str())
+            }
+            TableIndex::Patterns(set, keys) => set
+                .matches(key)
+                .iter()
+                .next()
+                .map(|index| keys[index].as_str()),
+        }
+    }
+}
+
+/// `lo..hi` including lo and excluding hi, either bound can be left out
+fn parse_range(key: &str) -> Result<(f64, f64), String> {
+    let (lo, hi) = key
+        .split_once("..")
+        .ok_or_else(|| format!("range {} is not lo..hi", key))?;
+    let bound = |text: &str, open: f64| match text.trim() {
+        "" => Ok(open),
+        text => text
+            .parse::<f64>()
+            .map_err(|e| format!("range {}: {}", key, e)),
+    };
+    Ok((bound(lo, f64::NEG_INFINITY)?, bound(hi, f64::INFINITY)?))
+}
+
+fn parse_ip(text: &str) -> Result<(bool, u128), String> {
+    match text.trim().parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Ok((true, u32::from(ip) as u128)),
+        Ok(IpAddr::V6(ip)) => Ok((false, u128::from(ip))),
+        Err(e) => Err(format!("{}: {}", text, e)),
+    }
+}
+
+/// `address/prefix`, a plain address is a network of one
+fn parse_cidr(key: &str) -> Result<(bool, u128, u8), String> {
+    let (address, prefix) = match key.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (key, None),
+    };
+    let (v4, bits) = parse_ip(address)?;
+    let width = if v4 { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .map_err(|e| format!("{}: {}", key, e))?,
+        None => width,
+    };
+    if prefix > width {
+        return Err(format!("{}: prefix longer than {} bits", key, width));
+    }
+    Ok((v4, bits, prefix))
+}
+
+fn mask(bits: u128, prefix: u8, v4: bool) -> u128 {
+    let width: u32 = if v4 { 32 } else { 128 };
+    let host_bits = width - prefix as u32;
+    if host_bits >= 128 {
+        0
+    } else {
+        bits >> host_bits << host_bits
+    }
+}
+
+/// `*` any run of characters, `?` any one character, `[...]` a character class,
+/// `[!...]` any character not in the class
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut in_class = false;
+    let mut class_start = false;
+    for c in glob.chars() {
+        let starting = class_start;
+        class_start = false;
+        match c {
+            '!' if starting => pattern.push('^'),
+            ']' if in_class && !starting => {
+                in_class = false;
+                pattern.push(c);
+            }
+            // nested classes and set operations mean something else to the regex crate
+            '\\' | '[' | '&' | '~' if in_class => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ if in_class => pattern.push(c),
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '[' => {
+                in_class = true;
+                class_start = true;
+                pattern.push(c);
+            }
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn index(mode: MatchMode, keys: &[&str]) -> (TableIndex, LookupDict) {
+        let entries: LookupDict = keys.iter().map(|k| (k.to_string(), json!(k))).collect();
+        (TableIndex::build(mode, &entries).unwrap(), entries)
+    }
+
+    #[test]
+    fn finds_the_most_specific_entry() {
+        let (ranges, entries) = index(MatchMode::Range, &["..300", "300..600", "600.."]);
+        assert_eq!(ranges.find(&entries, "299.5"), Some("..300"));
+        assert_eq!(ranges.find(&entries, "300"), Some("300..600"));
+        assert_eq!(ranges.find(&entries, "900"), Some("600.."));
+
+        let (prefixes, entries) = index(MatchMode::Prefix, &["44", "447", "1"]);
+        assert_eq!(prefixes.find(&entries, "447700900123"), Some("447"));
+        assert_eq!(prefixes.find(&entries, "33"), None);
+
+        let (cidrs, entries) = index(MatchMode::Cidr, &["10.0.0.0/8", "10.1.0.0/16", "::1"]);
+        assert_eq!(cidrs.find(&entries, "10.1.2.3"), Some("10.1.0.0/16"));
+        assert_eq!(cidrs.find(&entries, "10.2.2.3"), Some("10.0.0.0/8"));
+        assert_eq!(cidrs.find(&entries, "::1"), Some("::1"));
+        assert_eq!(cidrs.find(&entries, "192.168.0.1"), None);
+
+        let (globs, entries) = index(MatchMode::Glob, &["*.example.com", "*"]);
+        assert_eq!(
+            globs.find(&entries, "www.example.com"),
+            Some("*.example.com")
+        );
+        assert_eq!(globs.find(&entries, "example.org"), Some("*"));
+
+        let (classes, entries) = index(MatchMode::Glob, &["GB[!0-9]*"]);
+        assert_eq!(classes.find(&entries, "GBX1"), Some("GB[!0-9]*"));
+        assert_eq!(classes.find(&entries, "GB12"), None);
+
+        let overlapping: LookupDict = [("0..100", json!(1)), ("10..20", json!(2))]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        assert_eq!(
+            TableIndex::build(MatchMode::Range, &overlapping).unwrap_err(),
+            "ranges 0..100 and 10..20 overlap"
+        );
+    }
+}
@@ -1,31 +1,86 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject, SubschemaValidation};
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 use itertools::Itertools;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
+pub mod compile;
 pub mod loaders;
 pub mod lookups;
+mod matching;
+pub mod schemas;
+pub mod sources;
+pub mod walk;
 
-use crate::commands::execution::ActionExecution;
+use crate::commands::execution::{ActionExecution, ActionValue};
 use crate::commands::{Command, FiniCommand};
 use crate::context::PicoContext;
-use crate::runtime::PicoRuntime;
+use crate::errors::{PicoError, RuleFileError};
+use crate::runtime::{PicoRulesCache, PicoRuntime};
 use crate::values::PicoValue;
-use loaders::{FileLoader, PicoRuleLoader};
-use lookups::{get_external_lookup_names, LookupType, Lookups};
+use compile::Compiled;
+use loaders::PicoRuleLoader;
+use lookups::{get_external_lookups, ExternalTableSpec, LookupType, Lookups, TableRef};
+use schemas::RuleSchemas;
+use sources::{RuleSource, SourceLoader};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct StringOrSeq(#[serde(deserialize_with = "string_or_seq_string")] Vec<String>);
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StringOrSeq(#[serde(deserialize_with = "string_or_seq_string")] pub(crate) Vec<String>);
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct IncludeFile {
     pub include: String,
     // namespaces the included file can access
     pub with_namespaces: Option<StringOrSeq>,
 }
 
+// same shapes string_or_seq_string accepts
+impl JsonSchema for StringOrSeq {
+    fn schema_name() -> String {
+        String::from("StringOrSeq")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let any_of = vec![
+            gen.subschema_for::<String>(),
+            gen.subschema_for::<Vec<String>>(),
+        ];
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(any_of),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl IncludeFile {
+    /// namespaces passed on to the included file, limited to those the including file can access
+    fn granted_namespaces(&self, ctx: &PicoContext) -> HashSet<String> {
+        match &self.with_namespaces {
+            None => HashSet::new(),
+            Some(StringOrSeq(namespaces)) => namespaces
+                .iter()
+                .filter(|ns| {
+                    let allowed = ctx.ns_allowed(ns);
+                    if !allowed {
+                        warn!("can not grant namespace {} to {}", ns, self.include);
+                    }
+                    allowed
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
 fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -57,14 +112,14 @@ where
     deserializer.deserialize_any(StringOrVec(PhantomData))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum RuleFileRoot {
     Command(Command),
     IncludeFile(IncludeFile),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum RuleFileFini {
     FiniCommand(FiniCommand),
@@ -72,7 +127,7 @@ pub enum RuleFileFini {
 
 ///
 /// The internal reprsentation of a Pico rule file
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct RuleFile {
     #[serde(default = "RuleFile::default_version")]
     pub version: String,
@@ -83,6 +138,11 @@ pub struct RuleFile {
     // optional namespaces this file creates
     pub namespaces: Option<Vec<String>>,
 
+    /// JSON Schema that requests for this rule must match, checked before it runs
+    pub input_schema: Option<PicoValue>,
+    /// JSON Schema that the local variables must match once it has run
+    pub output_schema: Option<PicoValue>,
+
     pub root: Vec<RuleFileRoot>,
 
     pub fini: Option<Vec<RuleFileFini>>,
@@ -117,6 +177,8 @@ impl fmt::Display for RuleFile {
 enum FileStatus {
     Loaded,
     Missing,
+    // the file exists but could not be read or parsed
+    Failed(String),
 }
 
 #[derive(Debug)]
@@ -126,6 +188,8 @@ pub struct PicoRules {
     status: FileStatus,
 
     allowed_namespaces: HashSet<String>,
+    schemas: RuleSchemas,
+    compiled: Compiled,
 }
 
 impl fmt::Display for PicoRules {
@@ -155,6 +219,8 @@ impl Default for PicoRules {
             rulefile: None,
             status: FileStatus::Missing,
             allowed_namespaces: HashSet::new(),
+            schemas: RuleSchemas::default(),
+            compiled: Compiled::default(),
         }
     }
 }
@@ -186,6 +252,24 @@ impl PicoRules {
         }
     }
 
+    /// why the rule file is not loaded, `None` once it is
+    pub fn load_error(&self) -> Option<String> {
+        match &self.status {
+            FileStatus::Loaded => None,
+            FileStatus::Missing => Some(format!("{} does not exist", self.rulename)),
+            FileStatus::Failed(reason) => Some(reason.clone()),
+        }
+    }
+
+    /// what was prepared when the rule file was installed
+    pub fn compiled(&self) -> &Compiled {
+        &self.compiled
+    }
+
+    pub fn schemas(&self) -> &RuleSchemas {
+        &self.schemas
+    }
+
     pub fn set_rulename(mut self, rulename: &str) -> Self {
         self.rulename = rulename.to_string();
         self
@@ -195,9 +279,9 @@ impl PicoRules {
         &self.rulename
     }
 
-    pub fn external_lookups(&self) -> Vec<(&String, &String)> {
+    pub fn external_lookups(&self) -> Vec<(&String, ExternalTableSpec)> {
         match &self.rulefile {
-            Some(rf) => get_external_lookup_names(&rf.lookups),
+            Some(rf) => get_external_lookups(&rf.lookups),
             None => Vec::new(),
         }
     }
@@ -207,15 +291,23 @@ impl PicoRules {
 
         match loader.load() {
             Ok(rf) => {
-                get_external_lookup_names(&rf.lookups);
+                get_external_lookups(&rf.lookups);
 
                 self.rulefile = Some(rf);
                 self.status = FileStatus::Loaded;
+                self = self.setup_rules();
             }
             Err(x) => {
                 error!("failed to load {}", x);
+                let reason = match std::error::Error::source(&x) {
+                    Some(source) => format!("{}: {}", x, source),
+                    None => x.to_string(),
+                };
                 self.rulefile = None;
-                self.status = FileStatus::Missing;
+                self.status = match x {
+                    RuleFileError::MissingDocument { .. } => FileStatus::Missing,
+                    _ => FileStatus::Failed(reason),
+                };
             }
         }
 
@@ -226,11 +318,11 @@ impl PicoRules {
     pub fn install_rulefile(mut self, rulefile_name: &str, rulefile: RuleFile) -> Self {
         self.rulefile = Some(rulefile);
         self.status = FileStatus::Loaded;
-        self
+        self.setup_rules()
     }
 
-    // convenience, returns vec of filenames this file also includes
-    fn _included_filenames(&self) -> Vec<String> {
+    /// the rule files this file includes
+    pub fn included_filenames(&self) -> Vec<String> {
         match &self.rulefile {
             Some(rf) => rf
                 .root
@@ -260,78 +352,92 @@ impl PicoRules {
         include_sections
     }
 
-    pub fn setup_rules(self) -> Self {
+    /// a rule file can always access the namespaces it declares
+    pub fn setup_rules(mut self) -> Self {
         if let Some(rf) = &self.rulefile {
-            if let Some(_namespaces) = &rf.namespaces {}
+            if let Some(namespaces) = &rf.namespaces {
+                self.allowed_namespaces = namespaces.iter().cloned().collect();
+            }
+            match RuleSchemas::compile(rf) {
+                Ok(schemas) => self.schemas = schemas,
+                Err((field, e)) => error!("{} {} is not checked: {}", self.rulename, field, e),
+            }
+            self.compiled = compile::compile(&self.rulename, rf);
+            debug!("{} compiled {:?}", self.rulename, self.compiled);
         }
 
         self
     }
 
-    pub fn load_into_cache(filename: &str, cache: &mut HashMap<String, PicoRules>) {
-        let f = FileLoader::new(filename);
+    pub fn load_into_cache(
+        filename: &str,
+        source: &dyn RuleSource,
+        cache: &mut HashMap<String, Arc<PicoRules>>,
+    ) {
+        let f = SourceLoader::new(source, filename);
         let pr = PicoRules::new(filename).load_rulefile(f);
         for x in pr.include_sections().iter() {
-            PicoRules::load_into_cache(&x.include, cache);
+            if !cache.contains_key(&x.include) {
+                PicoRules::load_into_cache(&x.include, source, cache);
+            }
         }
-        cache.insert(filename.to_string(), pr);
+        cache.insert(filename.to_string(), Arc::new(pr));
     }
 
     pub fn upload_into_cache(
         filename: &str,
         rulefile: RuleFile,
-        cache: &mut HashMap<String, PicoRules>,
+        cache: &mut HashMap<String, Arc<PicoRules>>,
     ) {
         let pr = PicoRules::new(filename).install_rulefile(filename, rulefile);
-        cache.insert(filename.to_string(), pr);
+        cache.insert(filename.to_string(), Arc::new(pr));
     }
 
     pub fn run_with_context(&self, runtime: &PicoRuntime, ctx: &mut PicoContext) {
+        self.run_in_rule_set(runtime, &runtime.current().rules, ctx)
+    }
+
+    /// runs this rule, resolving includes from `rule_set` rather than the runtime's current rules
+    pub fn run_in_rule_set(
+        &self,
+        runtime: &PicoRuntime,
+        rule_set: &PicoRulesCache,
+        ctx: &mut PicoContext,
+    ) {
+        self.run_with_grants(runtime, rule_set, ctx, HashSet::new())
+    }
+
+    /// runs with access to the namespaces this file declares and those `granted` by the including file
+    fn run_with_grants(
+        &self,
+        runtime: &PicoRuntime,
+        rule_set: &PicoRulesCache,
+        ctx: &mut PicoContext,
+        granted: HashSet<String>,
+    ) {
+        ctx.ns_enter(self.allowed_namespaces.union(&granted).cloned().collect());
+        self.run_commands(runtime, rule_set, ctx);
+        ctx.ns_leave();
+    }
+
+    fn run_commands(
+        &self,
+        runtime: &PicoRuntime,
+        rule_set: &PicoRulesCache,
+        ctx: &mut PicoContext,
+    ) {
         trace!("RUNTIME: {:?}", runtime);
 
         match &self.rulefile {
             Some(rule_file) => {
                 for command in &rule_file.root {
-                    match command {
-                        RuleFileRoot::IncludeFile(i) => {
-                            // ensure the local scope variables are cleared
-                            ctx.local_clear();
-                            trace!("command include {:?}", i);
-                            /*
-                            if let Some(pico_rule) = self.rulefile_cache.get(&i.include) {
-                                pico_rule.run_with_context(runtime, ctx);
-                            } else {
-                                error!("Did not find expected rule {}", &i.include);
-                                trace!(" have these instead {:?}", self.rulefile_cache);
-                            }
-                            */
-
-                            match runtime.get_pico_rule(&i.include) {
-                                Some(next_rulefile) => {
-                                    info!("switching context to new pico.rule");
-                                    next_rulefile.run_with_context(runtime, ctx)
-                                }
-                                None => error!("no such rule {}", i.include),
-                            }
-                        }
-                        RuleFileRoot::Command(c) => match c.run_with_context(&self, runtime, ctx) {
-                            _ => debug!("root: command finished"),
-                        },
-                    }
-                }
-
-                if let Some(fini_secion) = &rule_file.fini {
-                    for fini_command in fini_secion {
-                        match fini_command {
-                            RuleFileFini::FiniCommand(fc) => {
-                                match fc.run_with_context(&self, runtime, ctx) {
-                                    Ok(data) => info!("returned data {:?}", data),
-                                    Err(e) => error!("fini failed {}", e),
-                                }
-                            }
-                        }
+                    if ctx.stopped().is_some() {
+                        debug!("{} stopped", self.rulename);
+                        break;
                     }
+                    self.run_root_entry(runtime, rule_set, ctx, command);
                 }
+                self.run_fini_commands(runtime, ctx);
 
                 //rule_file.run_with_context_new(state, ctx);
             }
@@ -341,6 +447,85 @@ impl PicoRules {
         };
     }
 
+    fn run_root_entry(
+        &self,
+        runtime: &PicoRuntime,
+        rule_set: &PicoRulesCache,
+        ctx: &mut PicoContext,
+        command: &RuleFileRoot,
+    ) {
+        match command {
+            RuleFileRoot::IncludeFile(i) => {
+                // ensure the local scope variables are cleared
+                ctx.local_clear();
+                trace!("command include {:?}", i);
+                /*
+                if let Some(pico_rule) = self.rulefile_cache.get(&i.include) {
+                    pico_rule.run_with_context(runtime, ctx);
+                } else {
+                    error!("Did not find expected rule {}", &i.include);
+                    trace!(" have these instead {:?}", self.rulefile_cache);
+                }
+                */
+
+                match rule_set.get(&i.include) {
+                    Some(next_rulefile) => {
+                        info!("switching context to new pico.rule");
+                        let granted = i.granted_namespaces(ctx);
+                        next_rulefile.run_with_grants(runtime, rule_set, ctx, granted)
+                    }
+                    None => error!("no such rule {}", i.include),
+                }
+            }
+            RuleFileRoot::Command(c) => match c.run_with_context(&self, runtime, ctx) {
+                Ok(ActionValue::Stop(reason)) => ctx.stop(reason),
+                Ok(_) => debug!("root: command finished"),
+                // the rest of the rule still runs, the caller sees what failed in `warnings`
+                Err(e) => ctx.warn(format!("{}: {}", self.get_rulename(), e)),
+            },
+        }
+    }
+
+    fn run_fini_commands(&self, runtime: &PicoRuntime, ctx: &mut PicoContext) {
+        let fini_section = self.rulefile.as_ref().and_then(|rf| rf.fini.as_ref());
+        for fini_command in fini_section.into_iter().flatten() {
+            match fini_command {
+                RuleFileFini::FiniCommand(fc) => match fc.run_with_context(&self, runtime, ctx) {
+                    Ok(data) => info!("returned data {:?}", data),
+                    Err(e) => error!("fini failed {}", e),
+                },
+            }
+        }
+    }
+
+    /// the root entries, for running a rule one entry at a time with `run_step`
+    pub fn steps(&self) -> &[RuleFileRoot] {
+        match &self.rulefile {
+            Some(rule_file) => &rule_file.root,
+            None => &[],
+        }
+    }
+
+    /// runs a single root entry with the namespace access of a full run, an include runs the whole file
+    pub fn run_step(
+        &self,
+        runtime: &PicoRuntime,
+        rule_set: &PicoRulesCache,
+        ctx: &mut PicoContext,
+        step: &RuleFileRoot,
+    ) {
+        ctx.ns_enter(self.allowed_namespaces.clone());
+        self.run_root_entry(runtime, rule_set, ctx, step);
+        ctx.ns_leave();
+    }
+
+    /// runs the fini section, which a full run does after the last root entry
+    pub fn run_fini(&self, runtime: &PicoRuntime, ctx: &mut PicoContext) {
+        ctx.ns_enter(self.allowed_namespaces.clone());
+        self.run_fini_commands(runtime, ctx);
+        ctx.ns_leave();
+    }
+
     pub fn is_ns_allowed(&self, requested_namespace: &str) -> bool {
         debug!("checking namespace access for [{}]", requested_namespace);
         trace!("Allowed namespaces {:?}", self.allowed_namespaces);
@@ -355,6 +540,38 @@ impl PicoRules {
         }
     }
 
+    /// the table a lookup names, whether it is written in the rule file or loaded by the runtime,
+    /// provided the running rule file can access one of the table's namespaces
+    pub fn resolve_table<'s>(
+        &'s self,
+        runtime: &'s PicoRuntime,
+        ctx: &mut PicoContext,
+        table: &str,
+    ) -> Result<TableRef<'s>, PicoError> {
+        let found = match (self.get_table(table), ctx.table_override(table)) {
+            // only tables the rule file declares can be replaced
+            (Some(_), Some(replaced)) => Some(TableRef::External(replaced)),
+            (Some(LookupType::InternalTable(internal)), None) => Some(TableRef::Internal(internal)),
+            (Some(LookupType::ExternalTable(file)), None) => {
+                external_table(runtime, ctx, file).map(TableRef::External)
+            }
+            (Some(LookupType::ExternalSpec(spec)), None) => {
+                external_table(runtime, ctx, &spec.cache_name()).map(TableRef::External)
+            }
+            (None, _) => None,
+        };
+        let lookup_table =
+            found.ok_or_else(|| PicoError::NoSuchValue(format!("No Such table {}", table)))?;
+
+        if !lookup_table.visible_to(ctx) {
+            return Err(PicoError::TableDenied {
+                table: table.to_string(),
+                namespaces: lookup_table.namespaces.clone().unwrap_or_default(),
+            });
+        }
+        Ok(lookup_table)
+    }
+
     pub fn table_lookup_value(&self, table: &str, key: &str) -> Option<&PicoValue> {
         match &self.rulefile {
             None => None,
@@ -368,3 +585,54 @@ impl PicoRules {
         }
     }
 }
+
+// a table of the version being run, or of the current version outside a run
+fn external_table(
+    runtime: &PicoRuntime,
+    ctx: &PicoContext,
+    cache_name: &str,
+) -> Option<Arc<lookups::LookupTable>> {
+    match ctx.lookups() {
+        Some(lookups) => lookups.get(cache_name).cloned(),
+        None => runtime.lookup_table(cache_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn namespaces_are_granted_to_includes() {
+        let rt = test_runtime(&[
+            (
+                "main.rule.json",
+                r#"{
+                    "namespaces": ["shared"],
+                    "root": [
+                        {"include": "granted.rule.json", "with_namespaces": "shared"},
+                        {"include": "denied.rule.json"},
+                        {"if": {"eq": [{"var": "shared::written"}, "granted"]},
+                         "then": {"set": ["seen", "granted"]}}
+                    ]
+                }"#,
+            ),
+            (
+                "granted.rule.json",
+                r#"{"root": [{"set": ["written", "granted"], "namespaces": ["shared"]}]}"#,
+            ),
+            (
+                "denied.rule.json",
+                r#"{"root": [{"set": ["other", "denied"], "namespaces": ["shared"]}]}"#,
+            ),
+        ]);
+        let result = test_run(&rt, "main.rule.json", json!({})).unwrap();
+
+        assert_eq!(
+            result["namespaced"],
+            json!({"shared": {"written": "granted"}})
+        );
+        assert_eq!(result["output"]["seen"], json!("granted"));
+    }
+}
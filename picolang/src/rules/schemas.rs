@@ -0,0 +1,138 @@
+//! The optional `input_schema` and `output_schema` of a rule file, JSON Schemas that
+//! requests and the local variables the rule sets are checked against.
+
+use jsonschema::Validator;
+use serde::Serialize;
+use std::fmt;
+
+use crate::rules::RuleFile;
+use crate::values::PicoValue;
+
+/// A value that does not match a schema, `path` is a JSON pointer into the value
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SchemaViolation {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// the compiled schemas of a rule file, a schema that is not given accepts anything
+#[derive(Debug, Default)]
+pub struct RuleSchemas {
+    input: Option<Validator>,
+    output: Option<Validator>,
+}
+
+impl RuleSchemas {
+    /// compiles both schemas, an error names the field of the schema that did not compile
+    pub fn compile(rulefile: &RuleFile) -> Result<Self, (&'static str, String)> {
+        let compile = |field, schema: &Option<PicoValue>| match schema {
+            Some(schema) => jsonschema::validator_for(schema)
+                .map(Some)
+                .map_err(|e| (field, e.to_string())),
+            None => Ok(None),
+        };
+        Ok(Self {
+            input: compile("input_schema", &rulefile.input_schema)?,
+            output: compile("output_schema", &rulefile.output_schema)?,
+        })
+    }
+
+    pub fn check_input(&self, input: &PicoValue) -> Result<(), Vec<SchemaViolation>> {
+        check(&self.input, input)
+    }
+
+    pub fn check_output(&self, output: &PicoValue) -> Result<(), Vec<SchemaViolation>> {
+        check(&self.output, output)
+    }
+}
+
+fn check(validator: &Option<Validator>, value: &PicoValue) -> Result<(), Vec<SchemaViolation>> {
+    let violations: Vec<SchemaViolation> = validator
+        .iter()
+        .flat_map(|validator| validator.iter_errors(value))
+        .map(|e| SchemaViolation {
+            path: e.instance_path.to_string(),
+            message: e.to_string(),
+        })
+        .collect();
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(violations),
+    }
+}
+
+/// Whether the variable `name`, a path such as `customer/address`, can be in an input
+/// matching `schema`.
+///
+/// Only objects that list their `properties` are followed, anything the schema does not
+/// describe is assumed to be allowed.
+pub fn schema_allows(schema: &PicoValue, name: &str) -> bool {
+    let mut schema = schema;
+    for segment in name.split('/') {
+        if let Some(items) = schema.get("items") {
+            if segment.parse::<usize>().is_ok() {
+                schema = items;
+                continue;
+            }
+        }
+        let properties = match schema.get("properties") {
+            Some(PicoValue::Object(properties)) => properties,
+            _ => return true,
+        };
+        schema = match (properties.get(segment), schema.get("additionalProperties")) {
+            (Some(property), _) => property,
+            (None, Some(PicoValue::Object(additional))) if !additional.is_empty() => return true,
+            // a field missing from the properties is most likely misspelt
+            (None, _) => return false,
+        };
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::RuntimeError;
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn inputs_and_outputs_are_checked_against_schemas() {
+        let rt = test_runtime(&[(
+            "typed.rule.json",
+            r#"{
+                "input_schema": {
+                    "type": "object",
+                    "properties": {"amount": {"type": "integer"}},
+                    "required": ["amount"]
+                },
+                "output_schema": {"properties": {"approved": {"type": "boolean"}}},
+                "root": [
+                    {"if": {"lt": [{"var": "amount"}, 100]}, "then": {"set": ["approved", true]}},
+                    {"if": {"gt": [{"var": "amount"}, 1000]}, "then": {"set": ["approved", "no"]}}
+                ]
+            }"#,
+        )]);
+        let run = |input| test_run(&rt, "typed.rule.json", input);
+
+        assert_eq!(
+            run(json!({"amount": 50})).unwrap()["output"]["approved"],
+            json!(true)
+        );
+        match run(json!({"amount": "50"})) {
+            Err(RuntimeError::InvalidInput { violations, .. }) => {
+                assert_eq!(violations[0].path, "/amount")
+            }
+            other => panic!("expected invalid input, got {:?}", other),
+        }
+        assert!(matches!(
+            run(json!({"amount": 5000})),
+            Err(RuntimeError::InvalidOutput { .. })
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::errors::RuleFileError;
+use crate::rules::loaders::{LoaderResult, PicoRuleLoader, RuleFormat};
+
+/// Where a rule set (rules, lookups and includes) is read from.
+///
+/// Documents are addressed by name, `include` and external lookup references
+/// are resolved against the same source as the file that names them.
+pub trait RuleSource: fmt::Debug + Send + Sync {
+    /// names of all documents the source holds
+    fn names(&self) -> Result<Vec<String>, RuleFileError>;
+
+    /// the raw text of one document
+    fn read(&self, name: &str) -> Result<String, RuleFileError>;
+}
+
+/// reads and parses a document from a source, the format is chosen from its name
+pub fn read_source_document<T: DeserializeOwned>(
+    source: &dyn RuleSource,
+    name: &str,
+) -> Result<T, RuleFileError> {
+    let format = RuleFormat::from_filename(name).unwrap_or_default();
+    let document = source.read(name)?;
+    format.parse(name, &document)
+}
+
+/// Loads a [`RuleFile`](crate::rules::RuleFile) by name from a [`RuleSource`]
+pub struct SourceLoader<'s> {
+    source: &'s dyn RuleSource,
+    name: String,
+}
+
+impl<'s> SourceLoader<'s> {
+    pub fn new(source: &'s dyn RuleSource, name: &str) -> Self {
+        Self {
+            source,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl<'s> PicoRuleLoader for SourceLoader<'s> {
+    fn filename_is(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn load(&self) -> LoaderResult {
+        read_source_document(self.source, &self.name)
+    }
+}
+
+/// Files in a single directory, not recursive
+#[derive(Debug)]
+pub struct DirectorySource {
+    directory: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(directory: &str) -> Self {
+        Self {
+            directory: PathBuf::from(directory),
+        }
+    }
+}
+
+impl RuleSource for DirectorySource {
+    fn names(&self) -> Result<Vec<String>, RuleFileError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<String, RuleFileError> {
+        fs::read_to_string(self.directory.join(name)).map_err(|source| RuleFileError::ReadError {
+            source,
+            filename: name.to_string(),
+        })
+    }
+}
+
+/// Documents held in memory, keyed by name
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    documents: HashMap<String, String>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// builder to add a document
+    pub fn with_document(mut self, name: &str, document: &str) -> Self {
+        self.insert(name, document);
+        self
+    }
+
+    pub fn insert(&mut self, name: &str, document: &str) {
+        self.documents
+            .insert(name.to_string(), document.to_string());
+    }
+}
+
+impl RuleSource for MemorySource {
+    fn names(&self) -> Result<Vec<String>, RuleFileError> {
+        Ok(self.documents.keys().cloned().collect())
+    }
+
+    fn read(&self, name: &str) -> Result<String, RuleFileError> {
+        self.documents
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RuleFileError::MissingDocument {
+                filename: name.to_string(),
+            })
+    }
+}
+
+/// A whole rule set shipped as one `.tar`, `.tar.gz`/`.tgz` or `.zip` file.
+///
+/// The archive is read into memory when opened, document names are the paths
+/// within the archive.
+#[cfg(feature = "archive")]
+#[derive(Debug)]
+pub struct ArchiveSource {
+    archive: String,
+    documents: MemorySource,
+}
+
+#[cfg(feature = "archive")]
+impl ArchiveSource {
+    pub fn open(archive: &str) -> Result<Self, RuleFileError> {
+        let file = fs::File::open(archive).map_err(|source| RuleFileError::ReadError {
+            source,
+            filename: archive.to_string(),
+        })?;
+
+        let documents = if archive.ends_with(".zip") {
+            Self::read_zip(archive, file)?
+        } else if archive.ends_with(".tar.gz") || archive.ends_with(".tgz") {
+            Self::read_tar(flate2::read::GzDecoder::new(file))?
+        } else if archive.ends_with(".tar") {
+            Self::read_tar(file)?
+        } else {
+            return Err(RuleFileError::Unsuported {
+                url: archive.to_string(),
+            });
+        };
+
+        info!("opened archive {}", archive);
+        Ok(Self {
+            archive: archive.to_string(),
+            documents,
+        })
+    }
+
+    fn read_tar<R: std::io::Read>(reader: R) -> Result<MemorySource, RuleFileError> {
+        use std::io::Read;
+
+        let mut documents = MemorySource::new();
+        let mut tarball = tar::Archive::new(reader);
+        for entry in tarball.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut document = String::new();
+            entry.read_to_string(&mut document)?;
+            documents.insert(name.trim_start_matches("./"), &document);
+        }
+        Ok(documents)
+    }
+
+    fn read_zip(archive: &str, file: fs::File) -> Result<MemorySource, RuleFileError> {
+        use std::io::Read;
+
+        let failure = |e: zip::result::ZipError| RuleFileError::SourceFailure {
+            url: archive.to_string(),
+            reason: e.to_string(),
+        };
+
+        let mut documents = MemorySource::new();
+        let mut zipped = zip::ZipArchive::new(file).map_err(failure)?;
+        for index in 0..zipped.len() {
+            let mut entry = zipped.by_index(index).map_err(failure)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let name = entry.name().to_string();
+            let mut document = String::new();
+            entry.read_to_string(&mut document)?;
+            documents.insert(&name, &document);
+        }
+        Ok(documents)
+    }
+}
+
+#[cfg(feature = "archive")]
+impl RuleSource for ArchiveSource {
+    fn names(&self) -> Result<Vec<String>, RuleFileError> {
+        self.documents.names()
+    }
+
+    fn read(&self, name: &str) -> Result<String, RuleFileError> {
+        let document = self.documents.read(name);
+        if document.is_err() {
+            warn!("{} not found in {}", name, self.archive);
+        }
+        document
+    }
+}
+
+/// Documents stored as rows of a SQLite table with `name` and `content` text columns
+#[cfg(feature = "sqlite")]
+pub struct SqliteSource {
+    database: String,
+    table: String,
+    connection: std::sync::Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl fmt::Debug for SqliteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SqliteSource: [{} table {}]", self.database, self.table)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSource {
+    pub fn open(database: &str, table: &str) -> Result<Self, RuleFileError> {
+        if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(RuleFileError::SourceFailure {
+                url: database.to_string(),
+                reason: format!("invalid table name {}", table),
+            });
+        }
+
+        let connection =
+            rusqlite::Connection::open(database).map_err(|e| RuleFileError::SourceFailure {
+                url: database.to_string(),
+                reason: e.to_string(),
+            })?;
+
+        Ok(Self {
+            database: database.to_string(),
+            table: table.to_string(),
+            connection: std::sync::Mutex::new(connection),
+        })
+    }
+
+    fn failure(&self, e: rusqlite::Error) -> RuleFileError {
+        RuleFileError::SourceFailure {
+            url: self.database.to_string(),
+            reason: e.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl RuleSource for SqliteSource {
+    fn names(&self) -> Result<Vec<String>, RuleFileError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(&format!("SELECT name FROM {}", self.table))
+            .map_err(|e| self.failure(e))?;
+        let names = statement
+            .query_map(rusqlite::NO_PARAMS, |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+            .map_err(|e| self.failure(e))?;
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<String, RuleFileError> {
+        use rusqlite::OptionalExtension;
+
+        let connection = self.connection.lock().unwrap();
+        let document: Option<String> = connection
+            .query_row(
+                &format!("SELECT content FROM {} WHERE name = ?1", self.table),
+                rusqlite::params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| self.failure(e))?;
+
+        document.ok_or_else(|| RuleFileError::MissingDocument {
+            filename: name.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn runs_a_rule_set_from_memory() {
+        let rt = test_runtime(&[
+            (
+                "main.rule.json",
+                r#"{
+                    "lookups": {"codes": "codes.lookup.json"},
+                    "root": [
+                        {"include": "child.rule.yaml"},
+                        {"set": ["code", {"lookup": ["codes", "one"]}]}
+                    ]
+                }"#,
+            ),
+            ("child.rule.yaml", "root:\n  - set: [child, ran]\n"),
+            (
+                "codes.lookup.json",
+                r#"{"entries": {"one": 1}, "default": 0}"#,
+            ),
+        ]);
+        let result = test_run(&rt, "main.rule.json", json!({})).unwrap();
+
+        assert_eq!(result["output"]["child"], json!("ran"));
+        assert_eq!(result["output"]["code"], json!(1));
+    }
+}
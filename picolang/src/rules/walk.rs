@@ -0,0 +1,191 @@
+//! Walks a [`RuleFile`](crate::rules::RuleFile) calling a [`RuleVisitor`] for every node,
+//! with the JSON pointer of that node within the rule file.
+
+use crate::commands::action::Action;
+use crate::commands::setting::Settable;
+use crate::commands::{Command, FiniCommand};
+use crate::conditions::Condition;
+use crate::rules::{IncludeFile, RuleFile, RuleFileFini, RuleFileRoot};
+use crate::values::{LookupKey, ValueProducer};
+
+/// Called for each node of a rule file, nodes borrow from the rule file for `'r`
+pub trait RuleVisitor<'r> {
+    fn visit_include(&mut self, _path: &str, _include: &'r IncludeFile) {}
+    fn visit_command(&mut self, _path: &str, _command: &'r Command) {}
+    fn visit_fini(&mut self, _path: &str, _command: &'r FiniCommand) {}
+    fn visit_condition(&mut self, _path: &str, _condition: &'r Condition) {}
+    fn visit_value(&mut self, _path: &str, _value: &'r ValueProducer) {}
+}
+
+pub fn walk_rulefile<'r, V: RuleVisitor<'r>>(rulefile: &'r RuleFile, visitor: &mut V) {
+    for (index, root) in rulefile.root.iter().enumerate() {
+        let path = format!("/root/{}", index);
+        match root {
+            RuleFileRoot::IncludeFile(include) => visitor.visit_include(&path, include),
+            RuleFileRoot::Command(command) => walk_command(&path, command, visitor),
+        }
+    }
+
+    if let Some(fini) = &rulefile.fini {
+        for (index, RuleFileFini::FiniCommand(command)) in fini.iter().enumerate() {
+            visitor.visit_fini(&format!("/fini/{}", index), command);
+        }
+    }
+}
+
+pub fn walk_command<'r, V: RuleVisitor<'r>>(path: &str, command: &'r Command, visitor: &mut V) {
+    visitor.visit_command(path, command);
+
+    match command {
+        Command::IfThenElse(ite) => {
+            walk_condition(&format!("{}/if", path), &ite.r#if, visitor);
+            walk_action(&format!("{}/then", path), &ite.r#then, visitor);
+            if let Some(else_branch) = &ite.r#else {
+                walk_action(&format!("{}/else", path), else_branch, visitor);
+            }
+        }
+        Command::Set(set) => match &set.set {
+            Settable::ValueProducing(_, value) => {
+                walk_value(&format!("{}/set/1", path), value, visitor)
+            }
+            Settable::Extractor(extract) => walk_value(
+                &format!("{}/set/extract/1", path),
+                &extract.extract.1,
+                visitor,
+            ),
+        },
+        Command::SetGlobal(set) => walk_value(
+            &format!("{}/set_global/1", path),
+            &set.set_global.1,
+            visitor,
+        ),
+        Command::StateSet(state) => {
+            walk_value(
+                &format!("{}/state_set/1", path),
+                &state.state_set.1,
+                visitor,
+            );
+            walk_value(
+                &format!("{}/state_set/2", path),
+                &state.state_set.2,
+                visitor,
+            );
+        }
+        Command::StateIncr(state) => walk_value(
+            &format!("{}/state_incr/1", path),
+            &state.state_incr.1,
+            visitor,
+        ),
+        Command::StateExpire(state) => walk_value(
+            &format!("{}/state_expire/1", path),
+            &state.state_expire.1,
+            visitor,
+        ),
+        Command::StateDelete(state) => walk_value(
+            &format!("{}/state_del/1", path),
+            &state.state_del.1,
+            visitor,
+        ),
+        Command::Log(_) | Command::DebugLog(_) | Command::BreakTo(_) | Command::Stop(_) => {}
+    }
+}
+
+pub fn walk_action<'r, V: RuleVisitor<'r>>(path: &str, action: &'r Action, visitor: &mut V) {
+    match action {
+        Action::Command(command) => walk_command(path, command, visitor),
+        Action::Commands(commands) => {
+            for (index, command) in commands.iter().enumerate() {
+                walk_command(&format!("{}/{}", path, index), command, visitor);
+            }
+        }
+    }
+}
+
+pub fn walk_condition<'r, V: RuleVisitor<'r>>(
+    path: &str,
+    condition: &'r Condition,
+    visitor: &mut V,
+) {
+    visitor.visit_condition(path, condition);
+
+    match condition {
+        Condition::Eq(c) => walk_pair(&format!("{}/eq", path), &c.eq, visitor),
+        Condition::Match(c) => walk_pair(&format!("{}/match", path), &c.r#match, visitor),
+        Condition::StartsWith(c) => {
+            walk_pair(&format!("{}/match_start", path), &c.match_start, visitor)
+        }
+        Condition::GreaterThan(c) => walk_pair(&format!("{}/gt", path), &c.gt, visitor),
+        Condition::LessThan(c) => walk_pair(&format!("{}/lt", path), &c.lt, visitor),
+        Condition::RegMatch(c) => {
+            walk_value(&format!("{}/regmatch/1", path), &c.regmatch.1, visitor)
+        }
+        Condition::And(c) => {
+            for (index, nested) in c.and.iter().enumerate() {
+                walk_condition(&format!("{}/and/{}", path, index), nested, visitor);
+            }
+        }
+        Condition::Or(c) => {
+            for (index, nested) in c.or.iter().enumerate() {
+                walk_condition(&format!("{}/or/{}", path, index), nested, visitor);
+            }
+        }
+        Condition::Not(c) => walk_condition(&format!("{}/not", path), &c.not, visitor),
+        Condition::Rate(c) => walk_value(&format!("{}/rate/0", path), &c.rate.0, visitor),
+        Condition::InTable(c) => {
+            walk_lookup_key(&format!("{}/in_table/1", path), &c.in_table.1, visitor)
+        }
+        Condition::VarExists(_) | Condition::VarMissing(_) => {}
+    }
+}
+
+pub fn walk_value<'r, V: RuleVisitor<'r>>(path: &str, value: &'r ValueProducer, visitor: &mut V) {
+    visitor.visit_value(path, value);
+
+    match value {
+        ValueProducer::Slice(slice) => {
+            walk_value(&format!("{}/slice/0", path), &slice.slice.0, visitor)
+        }
+        ValueProducer::ConCat(concat) => {
+            for (index, word) in concat.concat.iter().enumerate() {
+                walk_value(&format!("{}/concat/{}", path, index), word, visitor);
+            }
+        }
+        ValueProducer::Extract(extract) => {
+            walk_value(&format!("{}/extract/1", path), &extract.extract.1, visitor)
+        }
+        ValueProducer::StateGet(state) => walk_value(
+            &format!("{}/state_get/1", path),
+            &state.state_get.1,
+            visitor,
+        ),
+        ValueProducer::TableLookup(lookup) => {
+            walk_lookup_key(&format!("{}/lookup/1", path), &lookup.lookup.1, visitor)
+        }
+        ValueProducer::Pointer(_)
+        | ValueProducer::Global(_)
+        | ValueProducer::VarLookup(_)
+        | ValueProducer::LiteralString(_)
+        | ValueProducer::LiteralI64(_)
+        | ValueProducer::UnsupportedObject(_) => {}
+    }
+}
+
+fn walk_lookup_key<'r, V: RuleVisitor<'r>>(path: &str, key: &'r LookupKey, visitor: &mut V) {
+    match key {
+        LookupKey::Single(key) => walk_value(path, key, visitor),
+        LookupKey::Composite(keys) => {
+            for (index, key) in keys.iter().enumerate() {
+                walk_value(&format!("{}/{}", path, index), key, visitor);
+            }
+        }
+    }
+}
+
+fn walk_pair<'r, V: RuleVisitor<'r>>(
+    path: &str,
+    (lhs, rhs): &'r (ValueProducer, ValueProducer),
+    visitor: &mut V,
+) {
+    walk_value(&format!("{}/0", path), lhs, visitor);
+    walk_value(&format!("{}/1", path), rhs, visitor);
+}
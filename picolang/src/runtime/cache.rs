@@ -1,19 +1,21 @@
 use crate::errors::RuleFileError;
 use crate::rules::{
-  lookups::{load_into_cache, LookupTable},
+  lookups::{load_into_cache, ExternalTableSpec, LookupTable},
+  sources::RuleSource,
   PicoRules, RuleFile,
 };
 use crate::values::PicoValue;
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
 
 /// LookupCache: lookup tables that are shared between PicoRules
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LookupCache {
   // key: filename,
-  // value: lookup table
-  cache: HashMap<String, LookupTable>,
+  // value: lookup table, shared with copies of the cache
+  cache: HashMap<String, Arc<LookupTable>>,
 }
 impl Default for LookupCache {
   fn default() -> Self {
@@ -32,16 +34,58 @@ impl LookupCache {
     Default::default()
   }
 
-  pub fn load(&mut self, lookup_filename: &str) -> Result<(), RuleFileError> {
-    if self.cache.contains_key(lookup_filename) {
-      info!("Lookup cache already has {}", lookup_filename);
+  /// loads a lookup file as it is, CSV and JSON Lines tables keyed by their first column or `key` field
+  pub fn load(
+    &mut self,
+    lookup_filename: &str,
+    source: &dyn RuleSource,
+  ) -> Result<(), RuleFileError> {
+    self.load_spec(&ExternalTableSpec::from(lookup_filename), source)
+  }
+
+  pub fn load_spec(
+    &mut self,
+    spec: &ExternalTableSpec,
+    source: &dyn RuleSource,
+  ) -> Result<(), RuleFileError> {
+    if self.cache.contains_key(&spec.cache_name()) {
+      info!("Lookup cache already has {}", spec.cache_name());
     } else {
-      load_into_cache(lookup_filename, &mut self.cache);
+      let mut loaded = HashMap::new();
+      load_into_cache(spec, source, &mut loaded);
+      self.cache.extend(
+        loaded
+          .into_iter()
+          .map(|(name, table)| (name, Arc::new(table))),
+      );
     }
 
     Ok(())
   }
 
+  pub fn has(&self, lookup_filename: &str) -> bool {
+    self.cache.contains_key(lookup_filename)
+  }
+
+  pub fn insert(&mut self, lookup_filename: &str, table: LookupTable) {
+    self
+      .cache
+      .insert(lookup_filename.to_string(), Arc::new(table));
+  }
+
+  /// takes every table from `other`, replacing any already cached under the same name
+  pub fn merge(&mut self, other: LookupCache) {
+    self.cache.extend(other.cache);
+  }
+
+  pub fn names(&self) -> Vec<String> {
+    self.cache.keys().cloned().collect()
+  }
+
+  pub fn get(&self, lookup_filename: &str) -> Option<&Arc<LookupTable>> {
+    self.cache.get(lookup_filename)
+  }
+
   pub fn lookup(&self, lookup_filename: &str, key: &str) -> Option<&PicoValue> {
     self
       .cache
@@ -50,9 +94,10 @@ impl LookupCache {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PicoRulesCache {
-  cache: HashMap<String, PicoRules>,
+  // rules are shared between the rule set versions that contain them
+  cache: HashMap<String, Arc<PicoRules>>,
   including_paths: HashMap<String, Vec<String>>,
 }
 
@@ -76,27 +121,36 @@ impl PicoRulesCache {
     Default::default()
   }
   pub fn get(&self, name: &str) -> Option<&PicoRules> {
-    self.cache.get(name)
+    self.cache.get(name).map(|rules| rules.as_ref())
+  }
+
+  /// a rule that can outlive this cache, such as one kept after a newer version is published
+  pub fn get_shared(&self, name: &str) -> Option<Arc<PicoRules>> {
+    self.cache.get(name).cloned()
   }
 
   pub fn has(&self, rulename: &str) -> bool {
     self.cache.contains_key(rulename)
   }
 
-  pub fn values(&self) -> std::collections::hash_map::Values<String, PicoRules> {
-    self.cache.values()
+  pub fn values(&self) -> impl Iterator<Item = &PicoRules> {
+    self.cache.values().map(|rules| rules.as_ref())
   }
 
   pub fn filenames(&self) -> Vec<String> {
     self.cache.keys().cloned().collect()
   }
 
-  pub fn load(&mut self, entry_filename: &str) -> Result<(), RuleFileError> {
+  pub fn load(
+    &mut self,
+    entry_filename: &str,
+    source: &dyn RuleSource,
+  ) -> Result<(), RuleFileError> {
     if self.cache.contains_key(entry_filename) {
       info!("already have {}", entry_filename);
     } else {
       debug!("Attempting to load {}", entry_filename);
-      PicoRules::load_into_cache(entry_filename, &mut self.cache);
+      PicoRules::load_into_cache(entry_filename, source, &mut self.cache);
     }
     Ok(())
   }
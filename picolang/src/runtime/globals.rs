@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::errors::RuleFileError;
+use crate::rules::loaders::read_document;
+use crate::values::PicoValue;
+
+/// environment variables with this prefix become globals
+pub const GLOBAL_ENV_PREFIX: &str = "PICO_GLOBAL_";
+
+/// reads globals from a JSON, YAML or TOML document holding a single object/table
+pub fn globals_from_file(filename: &str) -> Result<HashMap<String, PicoValue>, RuleFileError> {
+    read_document(filename)
+}
+
+/// globals from the environment variables starting with `prefix`,
+/// `PICO_GLOBAL_MAX_SCORE=70` becomes the global `max_score` holding the number 70
+pub fn globals_from_env(prefix: &str) -> HashMap<String, PicoValue> {
+    globals_from_vars(prefix, std::env::vars())
+}
+
+fn globals_from_vars(
+    prefix: &str,
+    vars: impl Iterator<Item = (String, String)>,
+) -> HashMap<String, PicoValue> {
+    vars.filter_map(|(name, value)| {
+        let key = name.strip_prefix(prefix)?;
+        if key.is_empty() {
+            return None;
+        }
+        // numbers, booleans and JSON documents keep their type, anything else is a string
+        let value = serde_json::from_str(&value).unwrap_or(PicoValue::String(value));
+        Some((key.to_lowercase(), value))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{test_run, test_runtime, PicoRuntime};
+    use serde_json::json;
+
+    #[test]
+    fn env_values_keep_their_type() {
+        let vars = vec![
+            ("PICO_GLOBAL_REGION", "eu-west"),
+            ("PICO_GLOBAL_MAX_SCORE", "70"),
+            ("PICO_GLOBAL_", "ignored"),
+            ("HOME", "/root"),
+        ];
+        let globals = globals_from_vars(
+            GLOBAL_ENV_PREFIX,
+            vars.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+
+        assert_eq!(globals.len(), 2);
+        assert_eq!(globals["region"], json!("eu-west"));
+        assert_eq!(globals["max_score"], json!(70));
+    }
+
+    #[test]
+    fn globals_are_shared_between_requests() {
+        let rt = test_runtime(&[(
+            "count.rule.json",
+            r#"{
+                "root": [
+                    {"set": ["before", {"global": "count", "default": 0}]},
+                    {"set_global": ["count", {"var": "n"}]}
+                ]
+            }"#,
+        )])
+        .add_global("count", &PicoValue::from(0));
+        let run =
+            |rt: &PicoRuntime, n: i64| test_run(rt, "count.rule.json", json!({ "n": n })).unwrap();
+
+        // read only by default, the write fails and the global is unchanged
+        let denied = run(&rt, 1);
+        assert_eq!(
+            denied["warnings"],
+            json!([r#"count.rule.json: Global ["count"] is read only"#])
+        );
+        assert_eq!(rt.global_get("count"), Some(PicoValue::from(0)));
+
+        let rt = rt.enable_mutable_globals().add_fixed_globals(
+            vec![("region".to_string(), json!("eu"))]
+                .into_iter()
+                .collect(),
+        );
+        assert!(rt.global_set("region", &json!("us")).is_err());
+        assert_eq!(rt.globals()["region"], json!("eu"));
+        std::thread::scope(|scope| {
+            for n in 1..=8 {
+                let rt = &rt;
+                scope.spawn(move || run(rt, n));
+            }
+        });
+        let last = rt.global_get("count").and_then(|v| v.as_i64()).unwrap();
+        assert!((1..=8).contains(&last));
+        assert_eq!(run(&rt, 9)["output"]["before"], json!(last));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::conditions::rate::RateAlgorithm;
+
+// sweep idle counters after this many hits
+const SWEEP_EVERY: u64 = 4096;
+
+#[derive(Debug)]
+enum Counter {
+    // times of the most recent hits, at most limit + 1 are kept
+    Window(VecDeque<u64>),
+    Bucket { tokens: f64, updated: u64 },
+}
+
+#[derive(Debug)]
+struct Entry {
+    counter: Counter,
+    window: u64,
+    last_hit: u64,
+}
+
+/// Hit counters for the `rate` condition, keyed by counter name
+///
+/// Counters are held in memory and belong to one runtime, every process (or server replica)
+/// counts on its own and the counts are lost on restart.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    counters: Mutex<HashMap<String, Entry>>,
+    hits: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// records a hit at `now` (milliseconds), true when that makes more than `limit` hits in `window`
+    pub fn hit(
+        &self,
+        counter_name: &str,
+        algorithm: RateAlgorithm,
+        limit: u64,
+        window: u64,
+        now: u64,
+    ) -> bool {
+        let mut counters = self.counters.lock().unwrap();
+        if self.hits.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            // a counter idle for a whole window has nothing left to remember
+            counters.retain(|_, entry| entry.last_hit.saturating_add(entry.window) > now);
+        }
+
+        let entry = counters
+            .entry(counter_name.to_string())
+            .or_insert_with(|| Entry {
+                counter: Counter::new(algorithm, limit, now),
+                window,
+                last_hit: now,
+            });
+        if !entry.counter.is(algorithm) || entry.window != window {
+            debug!("counter {} changed shape, starting over", counter_name);
+            entry.counter = Counter::new(algorithm, limit, now);
+            entry.window = window;
+        }
+        entry.last_hit = now;
+
+        match &mut entry.counter {
+            Counter::Window(hits) => {
+                while hits
+                    .front()
+                    .map(|t| t.saturating_add(window) <= now)
+                    .unwrap_or(false)
+                {
+                    hits.pop_front();
+                }
+                hits.push_back(now);
+                while hits.len() as u64 > limit.saturating_add(1) {
+                    hits.pop_front();
+                }
+                hits.len() as u64 > limit
+            }
+            Counter::Bucket { tokens, updated } => {
+                // refills at limit tokens per window, never beyond limit
+                let refill =
+                    (now.saturating_sub(*updated)) as f64 * limit as f64 / window.max(1) as f64;
+                *tokens = (*tokens + refill).min(limit as f64);
+                *updated = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    false
+                } else {
+                    true
+                }
+            }
+        }
+    }
+}
+
+impl Counter {
+    fn new(algorithm: RateAlgorithm, limit: u64, now: u64) -> Self {
+        match algorithm {
+            RateAlgorithm::SlidingWindow => Counter::Window(VecDeque::new()),
+            RateAlgorithm::TokenBucket => Counter::Bucket {
+                tokens: limit as f64,
+                updated: now,
+            },
+        }
+    }
+
+    fn is(&self, algorithm: RateAlgorithm) -> bool {
+        matches!(
+            (self, algorithm),
+            (Counter::Window(_), RateAlgorithm::SlidingWindow)
+                | (Counter::Bucket { .. }, RateAlgorithm::TokenBucket)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_refills_over_the_window() {
+        let limiter = RateLimiter::new();
+        let hit = |now| limiter.hit("k", RateAlgorithm::TokenBucket, 2, 1000, now);
+
+        assert!(!hit(0));
+        assert!(!hit(0));
+        assert!(hit(0));
+        // half a window refills one of the two tokens
+        assert!(!hit(500));
+        assert!(hit(500));
+
+        // limits and windows at the end of the range do not overflow
+        let hit = |now| limiter.hit("max", RateAlgorithm::SlidingWindow, u64::MAX, u64::MAX, now);
+        assert!(!hit(u64::MAX));
+        assert!(!hit(u64::MAX));
+    }
+}
@@ -1,11 +1,17 @@
 use crate::errors::RuleFileError;
-use std::collections::HashMap;
-use std::fs;
-use std::{env, path::Path};
+use arc_swap::ArcSwap;
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 
+use crate::conditions::rate::RateAlgorithm;
 use crate::context::PicoContext;
-use crate::errors::RuntimeError;
-use crate::rules::lookups::LookupTable;
+use crate::errors::{PicoError, RuntimeError};
+use crate::lint::{Lint, Linter};
+use crate::rules::loaders::RuleFormat;
+use crate::rules::lookups::{is_lookup_file, LookupTable};
+use crate::rules::sources::{DirectorySource, RuleSource};
 use crate::rules::{PicoRules, RuleFile};
 use crate::values::PicoValue;
 
@@ -13,73 +19,133 @@ type Namespace = String;
 type VariableMap = HashMap<String, PicoValue>;
 
 mod cache;
-use cache::{LookupCache, PicoRulesCache};
+mod clock;
+mod globals;
+mod limiter;
+mod regexes;
+mod store;
+mod validation;
+mod versions;
+pub use cache::{LookupCache, PicoRulesCache};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use globals::{globals_from_env, globals_from_file, GLOBAL_ENV_PREFIX};
+use limiter::RateLimiter;
+pub(crate) use regexes::compile_pattern;
+use regexes::RegexCache;
+#[cfg(feature = "sqlite")]
+pub use store::SqliteStore;
+pub use store::{MemoryStore, StateStore};
+pub use validation::ValidationError;
+pub use versions::{RuleSetDiff, RuleSetHistory, RuleSetVersion, RuleSetVersionInfo};
+
+/// an upload that was published, with the lint warnings it was accepted with
+#[derive(Debug, Serialize)]
+pub struct Uploaded {
+    pub version: String,
+    pub warnings: Vec<Lint>,
+}
 
+/// Runs rules for any number of threads at once, share it with `Arc` or by reference.
+///
+/// Requests run against the current [`RuleSetVersion`] without taking a lock, publishing,
+/// rolling back and reloading swap in a new version and never wait for running requests.
 #[derive(Debug)]
-pub struct PicoRuntime<'a> {
-    pub globals: HashMap<String, PicoValue>,
-    pub namespaced_variables: HashMap<Namespace, VariableMap>,
-
-    pub namespaced_lookups: HashMap<(&'a str, &'a str), &'a LookupTable>,
+pub struct PicoRuntime {
+    // shared by every request, writes are serialised by the lock
+    pub globals: RwLock<HashMap<String, PicoValue>>,
+    // globals from configuration, never writable by rules
+    fixed_globals: HashSet<String>,
+    pub namespaced_variables: RwLock<HashMap<Namespace, VariableMap>>,
 
     feature_globals_readonly: bool,
     feature_namespaces: bool,
 
-    rules_directory: String,
-    rules_cache: PicoRulesCache,
+    rule_source: Box<dyn RuleSource>,
+    // the version requests run against, a copy of `versions.current()`
+    current: ArcSwap<RuleSetVersion>,
+    // changes to the rule set are made one at a time while holding this lock
+    versions: Mutex<RuleSetHistory>,
     default_rule_name: String,
 
-    lookup_cache: LookupCache,
+    // state that outlives a request
+    state: Box<dyn StateStore>,
+    rate_limiter: RateLimiter,
+    clock: Arc<dyn Clock>,
+
+    // `match` patterns compiled while the rules run, rather than when installed
+    regexes: RegexCache,
+    // the only namespaces an uploaded rule file may declare
+    upload_namespaces: HashSet<String>,
+}
+impl Default for PicoRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
-impl<'a> PicoRuntime<'a> {
+
+impl PicoRuntime {
     pub fn new() -> Self {
+        let versions = RuleSetHistory::new();
         Self {
-            globals: HashMap::new(),
-            namespaced_variables: HashMap::new(), // all namespaced variables
-            namespaced_lookups: HashMap::new(),
+            globals: RwLock::new(HashMap::new()),
+            fixed_globals: HashSet::new(),
+            namespaced_variables: RwLock::new(HashMap::new()), // all namespaced variables
             /// readonly globals by default
             feature_globals_readonly: true,
             /// enabled by default
             feature_namespaces: true,
-            rules_directory: String::from("rules/"),
-            rules_cache: PicoRulesCache::new(),
+            rule_source: Box::new(DirectorySource::new("rules/")),
+            current: ArcSwap::new(versions.current().clone()),
+            versions: Mutex::new(versions),
             default_rule_name: String::from("pico.rule.json"),
-            lookup_cache: LookupCache::new(),
+            state: Box::new(MemoryStore::new()),
+            rate_limiter: RateLimiter::new(),
+            clock: Arc::new(SystemClock),
+            regexes: RegexCache::default(),
+            upload_namespaces: HashSet::new(),
         }
     }
 
     // loads all rule and lookup files
-    pub fn load_rules(&mut self) -> Result<(), RuleFileError> {
-        let path = Path::new(&self.rules_directory);
-        env::set_current_dir(path)?;
-
-        for entry in fs::read_dir(".")? {
-            let entry = entry?;
-            let path = entry.path();
-            info!("FILENAME: {:?}", path.file_name());
-
-            if let Some(p) = path.file_name() {
-                if let Some(pp) = p.to_str() {
-                    if pp.ends_with(".lookup.json") {
-                        self.lookup_cache.load(pp)?;
-                    } else if pp.ends_with(".rule.json") {
-                        self.rules_cache.load(pp)?;
-                    }
-                }
+    fn read_rule_source(&self) -> Result<(PicoRulesCache, LookupCache), RuleFileError> {
+        let mut rules = PicoRulesCache::new();
+        let mut lookups = LookupCache::new();
+        for name in self.rule_source.names()? {
+            info!("FILENAME: {:?}", name);
+
+            if is_lookup_file(&name) {
+                lookups.load(&name, self.rule_source.as_ref())?;
+            } else if RuleFormat::is_kind(&name, "rule") {
+                rules.load(&name, self.rule_source.as_ref())?;
             }
         }
 
-        Ok(())
-    }
-
-    pub fn load_lookups(&mut self) -> Result<(), RuleFileError> {
-        for value in self.rules_cache.values() {
-            for (_table_name, file_name) in value.external_lookups() {
-                debug!("Loading external lookup from {}", file_name);
-                self.lookup_cache.load(file_name)?;
+        for value in rules.values() {
+            for (_table_name, spec) in value.external_lookups() {
+                debug!("Loading external lookup from {}", spec.file);
+                lookups.load_spec(&spec, self.rule_source.as_ref())?;
             }
         }
-        Ok(())
+        Ok((rules, lookups))
+    }
+
+    /// Reads every rule and lookup file from the rule source again and publishes them as a new version.
+    ///
+    /// Requests already running finish with the rules they started with.
+    pub fn reload(&self) -> Result<String, RuleFileError> {
+        let mut versions = self.versions.lock().unwrap();
+        let (rules, lookups) = self.read_rule_source()?;
+        versions.publish(rules, Arc::new(lookups));
+        Ok(self.install(&versions))
+    }
+
+    /// makes the history's current version the one requests run against, returns its id
+    fn install(&self, versions: &RuleSetHistory) -> String {
+        let version = versions.current().clone();
+        self.register_namespaces(&version.rules);
+        let id = version.id.clone();
+        self.current.store(version);
+        id
     }
 
     pub fn get_default_rule(&self) -> &str {
@@ -91,8 +157,77 @@ impl<'a> PicoRuntime<'a> {
         self
     }
 
-    pub fn set_rules_directory(mut self, directory: &str) -> Self {
-        self.rules_directory = String::from(directory);
+    pub fn set_rules_directory(self, directory: &str) -> Self {
+        self.set_rule_source(DirectorySource::new(directory))
+    }
+
+    /// builder to read rules, lookups and includes from somewhere other than a directory
+    pub fn set_rule_source(mut self, source: impl RuleSource + 'static) -> Self {
+        self.rule_source = Box::new(source);
+        self
+    }
+
+    /// builder to keep rule state somewhere other than memory, the store expires keys by the runtime's clock
+    pub fn set_state_store(mut self, store: impl StateStore + 'static) -> Self {
+        self.state = Box::new(store);
+        self.state.set_clock(self.clock.clone());
+        self
+    }
+
+    pub fn state(&self) -> &dyn StateStore {
+        self.state.as_ref()
+    }
+
+    /// builder to replace the system clock, rate conditions and state expiry read the time from it
+    pub fn set_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.state.set_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// records a hit on a rate counter, true when it has had more than `limit` hits within `seconds`
+    pub fn rate_exceeded(
+        &self,
+        counter: &str,
+        algorithm: RateAlgorithm,
+        limit: u64,
+        seconds: u64,
+    ) -> bool {
+        self.rate_limiter.hit(
+            counter,
+            algorithm,
+            limit,
+            seconds.saturating_mul(1000),
+            self.clock.now_millis(),
+        )
+    }
+
+    /// the compiled `match` pattern, a `dynamic` one was not a literal in the rule file
+    pub fn regex(&self, pattern: &str, dynamic: bool) -> Result<Arc<Regex>, PicoError> {
+        self.regexes.get(pattern, dynamic)
+    }
+
+    /// builder for how many dynamic `match` patterns are kept compiled, and how long one can be
+    pub fn set_regex_limits(mut self, cache_size: usize, max_pattern_length: usize) -> Self {
+        self.regexes.capacity = cache_size;
+        self.regexes.max_pattern_length = max_pattern_length;
+        self
+    }
+
+    /// `match` patterns must be literals, for rules run against untrusted input
+    pub fn forbid_dynamic_patterns(mut self) -> Self {
+        self.regexes.dynamic_allowed = false;
+        self
+    }
+
+    /// builder to let uploaded rule files declare these `namespaces`, an upload gets no other
+    /// namespace unless a published rule file grants it with `with_namespaces`
+    pub fn add_upload_namespaces(mut self, namespaces: impl IntoIterator<Item = String>) -> Self {
+        self.upload_namespaces.extend(namespaces);
         self
     }
 
@@ -106,60 +241,174 @@ impl<'a> PicoRuntime<'a> {
         self
     }
 
-    /// builder to add a global value
+    /// builder to add a global value, allowed even when globals are read only
     pub fn add_global(mut self, key: &str, value: &PicoValue) -> Self {
-        self.global_set(key, value);
+        self.globals
+            .get_mut()
+            .unwrap()
+            .insert(key.to_string(), value.clone());
         self
     }
-    pub fn initialise(mut self) -> Self {
+
+    /// builder to add globals that stay read only even when globals are mutable,
+    /// such as those from a config file or the environment
+    pub fn add_fixed_globals(mut self, globals: HashMap<String, PicoValue>) -> Self {
+        for (key, value) in globals {
+            self.fixed_globals.insert(key.clone());
+            self.globals.get_mut().unwrap().insert(key, value);
+        }
+        self
+    }
+
+    pub fn initialise(self) -> Self {
         info!("xx");
-        self.load_rules()
+        self.reload()
             .map_err(|x| error!("load rules failed {}", x))
             .unwrap();
-        self.load_lookups()
-            .map_err(|x| error!("load failed {}", x))
-            .unwrap();
+        self
+    }
 
-        let namespaces: Vec<String> = Vec::new();
+    // register namespaces declared by any rule file in `rules`
+    fn register_namespaces(&self, rules: &PicoRulesCache) {
+        let mut namespaces: Vec<String> = Vec::new();
+        for pico_rule in rules.values() {
+            pico_rule.all_namespace(&mut namespaces);
+        }
+        namespaces.sort();
+        namespaces.dedup();
 
         info!("ALL NAMESPACES {}", namespaces.join(","));
 
-        // register all declared namespaces
         for ns in namespaces {
-            self.add_namespace(&ns);
+            if !self.namespaced_variables.read().unwrap().contains_key(&ns) {
+                self.add_namespace(&ns);
+            }
         }
-
-        self
     }
 
     pub fn rule_file_names(&self) -> Vec<String> {
-        self.rules_cache.filenames()
+        self.current().rules.filenames()
     }
 
-    pub fn get_pico_rule(&self, rulename: &str) -> Option<&PicoRules> {
-        self.rules_cache.get(rulename)
+    /// the version requests are running against, it stays usable after a newer one is published
+    pub fn current(&self) -> Arc<RuleSetVersion> {
+        self.current.load_full()
     }
 
-    pub fn get_rule(&self, rulefile_name: &str) -> Option<&RuleFile> {
-        self.get_pico_rule(rulefile_name)
-            .and_then(|pico_rule| pico_rule.get_rulefile())
+    pub fn get_pico_rule(&self, rulename: &str) -> Option<Arc<PicoRules>> {
+        self.current().rules.get_shared(rulename)
     }
 
-    pub fn post_rule(&mut self, rulefile_name: &str, rulefile: RuleFile) {
-        self.rules_cache.upload(rulefile_name, rulefile);
+    /// publishes a new version with `rulefile` added or replaced, returns the version id
+    pub fn post_rule(&self, rulefile_name: &str, rulefile: RuleFile) -> String {
+        info!("Upload new rulefile {}", rulefile_name);
+        self.publish(vec![(rulefile_name.to_string(), rulefile)])
+    }
+
+    /// checks `rulefile` against the current rule set without installing anything
+    pub fn validate_rule(&self, rulefile_name: &str, rulefile: &RuleFile) -> Vec<ValidationError> {
+        let version = self.current();
+        validation::validate(
+            rulefile_name,
+            rulefile,
+            &version.rules,
+            &version.lookups,
+            None,
+            self.rule_source.as_ref(),
+        )
+        .errors
+    }
+
+    /// like `post_rule` but only publishes when `rulefile` validates and lints without errors,
+    /// includes and lookups it needs from the rule source are installed with it
+    pub fn upload_rule(
+        &self,
+        rulefile_name: &str,
+        rulefile: RuleFile,
+    ) -> Result<Uploaded, Vec<Lint>> {
+        // validated against the rules it will be published with
+        let mut versions = self.versions.lock().unwrap();
+        let validated = validation::validate(
+            rulefile_name,
+            &rulefile,
+            &versions.current().rules,
+            &versions.current().lookups,
+            Some(&self.upload_namespaces),
+            self.rule_source.as_ref(),
+        );
+        let (errors, warnings): (Vec<Lint>, Vec<Lint>) = validated
+            .errors
+            .into_iter()
+            .map(Lint::from)
+            .chain(Linter::new().lint(rulefile_name, &rulefile))
+            .partition(|lint| lint.is_error());
+        if !errors.is_empty() {
+            warn!("rejected {} with {} errors", rulefile_name, errors.len());
+            return Err(errors);
+        }
+
         info!("Upload new rulefile {}", rulefile_name);
+        // the tables it needs are published in the same version, a rollback drops them again
+        let mut lookups = LookupCache::clone(&versions.current().lookups);
+        lookups.merge(validated.lookups);
+        let mut rulefiles = validated.includes;
+        rulefiles.push((rulefile_name.to_string(), rulefile));
+        Ok(Uploaded {
+            version: self.publish_with(&mut versions, rulefiles, Arc::new(lookups)),
+            warnings,
+        })
+    }
+
+    /// adds or replaces several rule files as one new version, returns the version id
+    pub fn publish(&self, rulefiles: Vec<(String, RuleFile)>) -> String {
+        let mut versions = self.versions.lock().unwrap();
+        let lookups = versions.current().lookups.clone();
+        self.publish_with(&mut versions, rulefiles, lookups)
+    }
+
+    fn publish_with(
+        &self,
+        versions: &mut RuleSetHistory,
+        rulefiles: Vec<(String, RuleFile)>,
+        lookups: Arc<LookupCache>,
+    ) -> String {
+        let mut rules = versions.current().rules.clone();
+        for (rulefile_name, rulefile) in rulefiles {
+            rules.upload(&rulefile_name, rulefile);
+        }
+        versions.publish(rules, lookups);
+        self.install(versions)
+    }
+
+    pub fn current_version(&self) -> String {
+        self.current.load().id.clone()
+    }
+
+    pub fn versions(&self) -> Vec<RuleSetVersionInfo> {
+        self.versions.lock().unwrap().list()
+    }
+
+    pub fn diff_versions(&self, from: &str, to: &str) -> Result<RuleSetDiff, RuntimeError> {
+        self.versions.lock().unwrap().diff(from, to)
+    }
+
+    /// switches back to a previously published version, returns its full id
+    pub fn rollback(&self, version: &str) -> Result<String, RuntimeError> {
+        let mut versions = self.versions.lock().unwrap();
+        versions.rollback(version)?;
+        Ok(self.install(&versions))
     }
 
     pub fn make_ctx(&self, input_json: serde_json::Value) -> PicoContext {
         let mut pc = PicoContext::new();
-        for ns in self.namespaced_variables.keys() {
+        for ns in self.namespaced_variables.read().unwrap().keys() {
             pc.ns_add(ns);
         }
         pc.set_json(input_json)
     }
 
     pub fn has_rule(&self, rulename: &str) -> bool {
-        self.rules_cache.has(rulename)
+        self.current.load().rules.has(rulename)
     }
 
     pub fn exec_rule_with_context(
@@ -167,9 +416,78 @@ impl<'a> PicoRuntime<'a> {
         rulename: &str,
         ctx: &mut PicoContext,
     ) -> Result<HashMap<String, PicoValue>, RuntimeError> {
-        if let Some(ref pico_rule) = self.rules_cache.get(rulename) {
-            pico_rule.run_with_context(self, ctx);
-            Ok(ctx.get_final_ctx())
+        let version = self.current();
+        self.exec_in_version(&version, rulename, ctx)
+    }
+
+    /// runs a rule as it was in an earlier version, for comparing against the current one
+    pub fn exec_rule_at_version(
+        &self,
+        version: &str,
+        rulename: &str,
+        ctx: &mut PicoContext,
+    ) -> Result<HashMap<String, PicoValue>, RuntimeError> {
+        let rule_set_version = self.version(version)?;
+        self.exec_in_version(&rule_set_version, rulename, ctx)
+    }
+
+    /// a published version by its id, or an unambiguous prefix of it
+    pub fn version(&self, version: &str) -> Result<Arc<RuleSetVersion>, RuntimeError> {
+        Ok(self.versions.lock().unwrap().find(version)?.clone())
+    }
+
+    /// runs `rulename` once for each input, in parallel, the results are in the order of `inputs`
+    pub fn exec_batch(
+        &self,
+        rulename: &str,
+        inputs: Vec<serde_json::Value>,
+    ) -> Vec<Result<HashMap<String, PicoValue>, RuntimeError>> {
+        self.exec_batch_in(&self.current(), rulename, inputs)
+    }
+
+    /// like `exec_batch` but against `version`, so a batch split into parts can run every part
+    /// against the same rules even when a newer version is published between them
+    pub fn exec_batch_in(
+        &self,
+        version: &RuleSetVersion,
+        rulename: &str,
+        inputs: Vec<serde_json::Value>,
+    ) -> Vec<Result<HashMap<String, PicoValue>, RuntimeError>> {
+        inputs
+            .into_par_iter()
+            .map(|input| {
+                let mut ctx = self.make_ctx(input);
+                self.exec_in_version(version, rulename, &mut ctx)
+            })
+            .collect()
+    }
+
+    fn exec_in_version(
+        &self,
+        version: &RuleSetVersion,
+        rulename: &str,
+        ctx: &mut PicoContext,
+    ) -> Result<HashMap<String, PicoValue>, RuntimeError> {
+        let rule_set = &version.rules;
+        if let Some(pico_rule) = rule_set.get(rulename) {
+            ctx.set_lookups(version.lookups.clone());
+            let input = ctx.input_json.clone().unwrap_or_default();
+            if let Err(violations) = pico_rule.schemas().check_input(&input) {
+                return Err(RuntimeError::InvalidInput {
+                    rulename: rulename.to_string(),
+                    violations,
+                });
+            }
+            pico_rule.run_in_rule_set(self, rule_set, ctx);
+            let final_ctx = ctx.get_final_ctx();
+            let output = final_ctx.get("output").cloned().unwrap_or_default();
+            if let Err(violations) = pico_rule.schemas().check_output(&output) {
+                return Err(RuntimeError::InvalidOutput {
+                    rulename: rulename.to_string(),
+                    violations,
+                });
+            }
+            Ok(final_ctx)
         } else {
             Err(RuntimeError::NoSuchRule {
                 rulename: rulename.to_string(),
@@ -185,41 +503,58 @@ impl<'a> PicoRuntime<'a> {
         self.exec_rule_with_context(&self.default_rule_name, ctx)
     }
 
-    pub fn global_get(&self, key: &str) -> Option<&PicoValue> {
-        self.globals.get(key)
+    pub fn global_get(&self, key: &str) -> Option<PicoValue> {
+        self.globals.read().unwrap().get(key).cloned()
     }
 
-    pub fn global_set(&mut self, key: &str, value: &PicoValue) {
-        if self.feature_globals_readonly {
-            warn!(
-                "Global values are imutable, attempted to set {} = {}",
-                key, value
-            );
-        } else {
-            self.globals.insert(key.to_string(), value.clone());
+    /// a copy of every global
+    pub fn globals(&self) -> HashMap<String, PicoValue> {
+        self.globals.read().unwrap().clone()
+    }
+
+    /// Replaces a global for every request that reads it afterwards.
+    ///
+    /// Each write is atomic and concurrent writes are applied one at a time, the last one wins.
+    /// Requests already running see the new value the next time they read the global.
+    pub fn global_set(&self, key: &str, value: &PicoValue) -> Result<(), PicoError> {
+        if self.feature_globals_readonly || self.fixed_globals.contains(key) {
+            warn!("Global value is imutable, attempted to set {}", key);
+            return Err(PicoError::ReadOnlyGlobal {
+                key: key.to_string(),
+            });
         }
+        self.globals
+            .write()
+            .unwrap()
+            .insert(key.to_string(), value.clone());
+        Ok(())
     }
 
-    pub fn new_namespace(&mut self, name: &str) {
-        info!("NAMESPACES {:?}", self.namespaced_variables);
-        self.namespaced_variables
-            .insert(name.to_string(), HashMap::new());
+    pub fn new_namespace(&self, name: &str) {
+        let mut namespaced_variables = self.namespaced_variables.write().unwrap();
+        info!("NAMESPACES {:?}", namespaced_variables);
+        namespaced_variables.insert(name.to_string(), HashMap::new());
     }
 
-    pub fn add_namespace(&mut self, name: &str) {
-        if !self.namespaced_variables.contains_key(name) {
+    pub fn add_namespace(&self, name: &str) {
+        if !self.namespaced_variables.read().unwrap().contains_key(name) {
             self.new_namespace(name);
         } else {
             warn!("Attempt to redeclare namespace: [{}]", name);
         }
     }
 
-    pub fn ns_get(&self, ns: &str, key: &str) -> Option<&PicoValue> {
-        self.namespaced_variables.get(ns).and_then(|hm| hm.get(key))
+    pub fn ns_get(&self, ns: &str, key: &str) -> Option<PicoValue> {
+        self.namespaced_variables
+            .read()
+            .unwrap()
+            .get(ns)
+            .and_then(|hm| hm.get(key))
+            .cloned()
     }
 
-    pub fn ns_set(&mut self, ns: &str, key: &str, value: &PicoValue) {
-        if let Some(ns_map) = self.namespaced_variables.get_mut(ns) {
+    pub fn ns_set(&self, ns: &str, key: &str, value: &PicoValue) {
+        if let Some(ns_map) = self.namespaced_variables.write().unwrap().get_mut(ns) {
             ns_map.insert(key.to_string(), value.clone());
         } else {
             warn!(
@@ -229,7 +564,90 @@ impl<'a> PicoRuntime<'a> {
         }
     }
 
-    pub fn table_lookup(&self, table_filename: &str, key: &str) -> Option<&PicoValue> {
-        self.lookup_cache.lookup(table_filename, key)
+    /// an external lookup table of the current version by the name it is cached under
+    pub fn lookup_table(&self, cache_name: &str) -> Option<Arc<LookupTable>> {
+        self.current.load().lookups.get(cache_name).cloned()
+    }
+
+    pub fn table_lookup(&self, table_filename: &str, key: &str) -> Option<PicoValue> {
+        self.current
+            .load()
+            .lookups
+            .lookup(table_filename, key)
+            .cloned()
+    }
+}
+
+/// a runtime over an in-memory rule set of (filename, document) pairs, for tests
+#[cfg(test)]
+pub(crate) fn test_runtime(documents: &[(&str, &str)]) -> PicoRuntime {
+    let source = documents.iter().fold(
+        crate::rules::sources::MemorySource::new(),
+        |source, (name, document)| source.with_document(name, document),
+    );
+    PicoRuntime::new().set_rule_source(source).initialise()
+}
+
+/// runs `rulename` on `input` in a fresh context, for tests
+#[cfg(test)]
+pub(crate) fn test_run(
+    rt: &PicoRuntime,
+    rulename: &str,
+    input: serde_json::Value,
+) -> Result<HashMap<String, PicoValue>, RuntimeError> {
+    rt.exec_rule_with_context(rulename, &mut rt.make_ctx(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+    use serde_json::json;
+
+    #[test]
+    fn requests_run_while_rules_are_published() {
+        let rt = test_runtime(&[("a.rule.json", r#"{"root": [{"set": ["answer", "first"]}]}"#)]);
+        let rule = |answer: &str| {
+            let document = format!(r#"{{"root": [{{"set": ["answer", "{}"]}}]}}"#, answer);
+            StringLoader::new("a.rule.json", &document).load().unwrap()
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        let result = test_run(&rt, "a.rule.json", json!({})).unwrap();
+                        let answer = result["output"]["answer"].as_str().unwrap().to_string();
+                        assert!(answer == "first" || answer == "second");
+                    }
+                });
+            }
+            rt.post_rule("a.rule.json", rule("second"));
+        });
+
+        let published = rt.current_version();
+        assert_eq!(rt.reload().unwrap(), rt.current_version());
+        assert_ne!(rt.current_version(), published);
+        let result = test_run(&rt, "a.rule.json", json!({})).unwrap();
+        assert_eq!(result["output"]["answer"], json!("first"));
+    }
+
+    #[test]
+    fn batches_keep_their_order() {
+        let rt = test_runtime(&[(
+            "echo.rule.json",
+            r#"{"root": [{"set": ["n", {"var": "n"}]}]}"#,
+        )]);
+
+        let inputs = (0..100).map(|n| json!({ "n": n })).collect();
+        let results = rt.exec_batch("echo.rule.json", inputs);
+        let seen: Vec<PicoValue> = results
+            .into_iter()
+            .map(|result| result.unwrap()["output"]["n"].clone())
+            .collect();
+        assert_eq!(seen, (0..100).map(|n| json!(n)).collect::<Vec<_>>());
+
+        let missing = rt.exec_batch("missing.rule.json", vec![json!({})]);
+        assert!(matches!(missing[0], Err(RuntimeError::NoSuchRule { .. })));
     }
 }
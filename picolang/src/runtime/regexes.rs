@@ -0,0 +1,174 @@
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::errors::PicoError;
+
+const DEFAULT_CACHE_SIZE: usize = 256;
+const DEFAULT_MAX_PATTERN_LENGTH: usize = 1024;
+// compiled size of a dynamic pattern, well below the regex crate's own limit
+const DYNAMIC_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug)]
+struct Entry {
+    regex: Arc<Regex>,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_pattern: HashMap<String, Entry>,
+    // patterns by when they were last used, the first is the one to drop
+    by_use: BTreeMap<u64, String>,
+    uses: u64,
+}
+
+impl Entries {
+    fn touch(&mut self, pattern: &str) -> Option<Arc<Regex>> {
+        self.uses += 1;
+        let now = self.uses;
+        let entry = self.by_pattern.get_mut(pattern)?;
+        let pattern = self.by_use.remove(&entry.last_used)?;
+        entry.last_used = now;
+        self.by_use.insert(now, pattern);
+        Some(entry.regex.clone())
+    }
+
+    fn insert(&mut self, pattern: &str, regex: Arc<Regex>, capacity: usize) {
+        while self.by_pattern.len() >= capacity {
+            match self.by_use.pop_first() {
+                Some((_, oldest)) => self.by_pattern.remove(&oldest),
+                None => break,
+            };
+        }
+        self.uses += 1;
+        self.by_use.insert(self.uses, pattern.to_string());
+        self.by_pattern.insert(
+            pattern.to_string(),
+            Entry {
+                regex,
+                last_used: self.uses,
+            },
+        );
+    }
+}
+
+/// Compiled `match` patterns that are not known until the rules run, least recently used
+/// patterns are dropped once there are `capacity` of them
+#[derive(Debug)]
+pub struct RegexCache {
+    entries: Mutex<Entries>,
+    pub(super) capacity: usize,
+    pub(super) max_pattern_length: usize,
+    pub(super) dynamic_allowed: bool,
+}
+
+impl Default for RegexCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE, DEFAULT_MAX_PATTERN_LENGTH, true)
+    }
+}
+
+impl RegexCache {
+    pub fn new(capacity: usize, max_pattern_length: usize, dynamic_allowed: bool) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+            max_pattern_length,
+            dynamic_allowed,
+        }
+    }
+
+    /// the compiled `pattern`, a `dynamic` one came from the input, state or variables
+    /// and is held to the limits
+    pub fn get(&self, pattern: &str, dynamic: bool) -> Result<Arc<Regex>, PicoError> {
+        if dynamic {
+            if !self.dynamic_allowed {
+                return Err(PicoError::DynamicPatternDenied {
+                    pattern: pattern.to_string(),
+                });
+            }
+            if pattern.len() > self.max_pattern_length {
+                return Err(PicoError::InvalidPattern {
+                    pattern: pattern.chars().take(64).collect(),
+                    reason: format!("longer than {} bytes", self.max_pattern_length),
+                });
+            }
+        }
+
+        if let Some(regex) = self.entries.lock().unwrap().touch(pattern) {
+            return Ok(regex);
+        }
+
+        // compiled without the lock, a slow pattern only holds up the request that uses it
+        let regex = Arc::new(compile_pattern(pattern, dynamic)?);
+        if self.capacity == 0 {
+            return Ok(regex);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        // another request may have compiled it meanwhile
+        if let Some(cached) = entries.touch(pattern) {
+            return Ok(cached);
+        }
+        entries.insert(pattern, regex.clone(), self.capacity);
+        Ok(regex)
+    }
+}
+
+/// compiles a `match` or `regmatch` pattern, a `dynamic` one is held to a smaller compiled size,
+/// rules, the cache and the linter all compile through here so errors read the same
+pub(crate) fn compile_pattern(pattern: &str, dynamic: bool) -> Result<Regex, PicoError> {
+    let mut builder = RegexBuilder::new(pattern);
+    if dynamic {
+        builder.size_limit(DYNAMIC_SIZE_LIMIT);
+    }
+    builder.build().map_err(|e| PicoError::InvalidPattern {
+        pattern: pattern.to_string(),
+        reason: regex_error(&e),
+    })
+}
+
+// syntax errors draw the pattern over several lines, the last one says what is wrong
+fn regex_error(e: &regex::Error) -> String {
+    let message = e.to_string();
+    let reason = message.lines().last().unwrap_or_default();
+    reason.trim_start_matches("error: ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_is_dropped() {
+        let cache = RegexCache::new(2, 8, true);
+        cache.get("^a", true).unwrap();
+        cache.get("^b", true).unwrap();
+        cache.get("^a", true).unwrap();
+        cache.get("^c", true).unwrap();
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.by_pattern.len(), 2);
+        assert_eq!(entries.by_use.len(), 2);
+        assert!(entries.by_pattern.contains_key("^a"));
+        assert!(!entries.by_pattern.contains_key("^b"));
+        drop(entries);
+
+        assert!(matches!(
+            cache.get("(unclosed", true),
+            Err(PicoError::InvalidPattern { .. })
+        ));
+        assert!(matches!(
+            cache.get("^too long pattern", true),
+            Err(PicoError::InvalidPattern { .. })
+        ));
+        // literal patterns in rule files are not limited
+        assert!(cache.get("^too long pattern", false).is_ok());
+
+        let forbidden = RegexCache::new(2, 8, false);
+        assert!(matches!(
+            forbidden.get("^a", true),
+            Err(PicoError::DynamicPatternDenied { .. })
+        ));
+        assert!(forbidden.get("^a", false).is_ok());
+    }
+}
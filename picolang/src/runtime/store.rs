@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::clock::{Clock, SystemClock};
+use crate::errors::StateError;
+use crate::values::PicoValue;
+
+/// Values that outlive a single request, keyed by namespace and key.
+///
+/// Each call is atomic with respect to every other call on the same store,
+/// expired keys behave as if they were never set.
+pub trait StateStore: fmt::Debug + Send + Sync {
+    fn get(&self, ns: &str, key: &str) -> Result<Option<PicoValue>, StateError>;
+
+    /// stores `value`, replacing the key's expiry with `ttl`
+    fn set(
+        &self,
+        ns: &str,
+        key: &str,
+        value: &PicoValue,
+        ttl: Option<Duration>,
+    ) -> Result<(), StateError>;
+
+    /// adds `by` to an integer and returns the result, a missing key counts from 0 and
+    /// expires after `ttl`, an existing key keeps its expiry
+    fn incr(&self, ns: &str, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64, StateError>;
+
+    /// false when there is no such key
+    fn expire(&self, ns: &str, key: &str, ttl: Duration) -> Result<bool, StateError>;
+
+    /// false when there is no such key
+    fn delete(&self, ns: &str, key: &str) -> Result<bool, StateError>;
+
+    /// drops every expired key, stores also do this on their own about once a minute
+    fn purge_expired(&self) -> Result<(), StateError> {
+        Ok(())
+    }
+
+    /// expires keys by `clock`, the runtime passes its own clock on
+    fn set_clock(&mut self, _clock: Arc<dyn Clock>) {}
+}
+
+// how often a store drops expired keys that nobody asked for again, in milliseconds
+const PURGE_INTERVAL: u64 = 60_000;
+
+fn expires_at(now: u64, ttl: Option<Duration>) -> Option<u64> {
+    ttl.map(|ttl| now.saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX)))
+}
+
+// true at most once every `PURGE_INTERVAL`, for the caller that should purge
+fn purge_due(last_purge: &AtomicU64, now: u64) -> bool {
+    let last = last_purge.load(Ordering::SeqCst);
+    now.saturating_sub(last) >= PURGE_INTERVAL
+        && last_purge
+            .compare_exchange(last, now, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+}
+
+fn add(ns: &str, key: &str, total: i64, by: i64) -> Result<i64, StateError> {
+    total.checked_add(by).ok_or_else(|| StateError::Overflow {
+        namespace: ns.to_string(),
+        key: key.to_string(),
+    })
+}
+
+fn as_integer(ns: &str, key: &str, value: &PicoValue) -> Result<i64, StateError> {
+    value.as_i64().ok_or_else(|| StateError::NotANumber {
+        namespace: ns.to_string(),
+        key: key.to_string(),
+    })
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: PicoValue,
+    // unix milliseconds
+    expires: Option<u64>,
+}
+
+impl Entry {
+    fn is_live(&self, now: u64) -> bool {
+        self.expires.map(|expires| expires > now).unwrap_or(true)
+    }
+}
+
+/// State held in the process, lost on restart
+#[derive(Debug)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<(String, String), Entry>>,
+    clock: Arc<dyn Clock>,
+    last_purge: AtomicU64,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+            last_purge: AtomicU64::new(0),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// builder to expire keys by a clock other than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// keys held, expired keys included until they are purged
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn with_live_entry<R>(
+        &self,
+        ns: &str,
+        key: &str,
+        f: impl FnOnce(&mut HashMap<(String, String), Entry>, (String, String), u64) -> R,
+    ) -> R {
+        let now = self.clock.now_millis();
+        let mut entries = self.entries.lock().unwrap();
+        if purge_due(&self.last_purge, now) {
+            entries.retain(|_, entry| entry.is_live(now));
+        }
+        let entry_key = (ns.to_string(), key.to_string());
+        if entries
+            .get(&entry_key)
+            .map(|entry| !entry.is_live(now))
+            .unwrap_or(false)
+        {
+            entries.remove(&entry_key);
+        }
+        f(&mut entries, entry_key, now)
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, ns: &str, key: &str) -> Result<Option<PicoValue>, StateError> {
+        Ok(self.with_live_entry(ns, key, |entries, entry_key, _| {
+            entries.get(&entry_key).map(|entry| entry.value.clone())
+        }))
+    }
+
+    fn set(
+        &self,
+        ns: &str,
+        key: &str,
+        value: &PicoValue,
+        ttl: Option<Duration>,
+    ) -> Result<(), StateError> {
+        self.with_live_entry(ns, key, |entries, entry_key, now| {
+            let entry = Entry {
+                value: value.clone(),
+                expires: expires_at(now, ttl),
+            };
+            entries.insert(entry_key, entry);
+        });
+        Ok(())
+    }
+
+    fn incr(&self, ns: &str, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64, StateError> {
+        self.with_live_entry(ns, key, |entries, entry_key, now| {
+            let entry = entries.entry(entry_key).or_insert_with(|| Entry {
+                value: PicoValue::from(0),
+                expires: expires_at(now, ttl),
+            });
+            let total = add(ns, key, as_integer(ns, key, &entry.value)?, by)?;
+            entry.value = PicoValue::from(total);
+            Ok(total)
+        })
+    }
+
+    fn expire(&self, ns: &str, key: &str, ttl: Duration) -> Result<bool, StateError> {
+        Ok(self.with_live_entry(ns, key, |entries, entry_key, now| {
+            match entries.get_mut(&entry_key) {
+                Some(entry) => {
+                    entry.expires = expires_at(now, Some(ttl));
+                    true
+                }
+                None => false,
+            }
+        }))
+    }
+
+    fn delete(&self, ns: &str, key: &str) -> Result<bool, StateError> {
+        Ok(self.with_live_entry(ns, key, |entries, entry_key, _| {
+            entries.remove(&entry_key).is_some()
+        }))
+    }
+
+    fn purge_expired(&self) -> Result<(), StateError> {
+        let now = self.clock.now_millis();
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.is_live(now));
+        Ok(())
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+/// State kept in a SQLite database, shared by every process using the same file
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    database: String,
+    connection: Mutex<rusqlite::Connection>,
+    clock: Arc<dyn Clock>,
+    last_purge: AtomicU64,
+}
+
+#[cfg(feature = "sqlite")]
+impl fmt::Debug for SqliteStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SqliteStore: [{}]", self.database)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// opens or creates the database and its `pico_state` table
+    pub fn open(database: &str) -> Result<Self, StateError> {
+        let connection = rusqlite::Connection::open(database).map_err(failure)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS pico_state (
+                    namespace TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    expires INTEGER,
+                    PRIMARY KEY (namespace, key)
+                );
+                CREATE INDEX IF NOT EXISTS pico_state_expires ON pico_state (expires);",
+            )
+            .map_err(failure)?;
+
+        Ok(Self {
+            database: database.to_string(),
+            connection: Mutex::new(connection),
+            clock: Arc::new(SystemClock),
+            last_purge: AtomicU64::new(0),
+        })
+    }
+
+    /// builder to expire keys by a clock other than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// runs `f` in an immediate transaction, so other processes can not interleave,
+    /// expired rows are left in place and skipped by every statement
+    fn transaction<R>(
+        &self,
+        f: impl FnOnce(&rusqlite::Transaction, i64) -> Result<R, StateError>,
+    ) -> Result<R, StateError> {
+        let now = self.clock.now_millis();
+        if purge_due(&self.last_purge, now) {
+            self.purge_expired()?;
+        }
+        let mut connection = self.connection.lock().unwrap();
+        let tx = connection
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .map_err(failure)?;
+        let result = f(&tx, sql_millis(now))?;
+        tx.commit().map_err(failure)?;
+        Ok(result)
+    }
+
+    fn read(
+        connection: &rusqlite::Connection,
+        ns: &str,
+        key: &str,
+        now: i64,
+    ) -> Result<Option<PicoValue>, StateError> {
+        use rusqlite::OptionalExtension;
+
+        let stored: Option<String> = connection
+            .query_row(
+                "SELECT value FROM pico_state WHERE namespace = ?1 AND key = ?2
+                    AND (expires IS NULL OR expires > ?3)",
+                rusqlite::params![ns, key, now],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(failure)?;
+
+        stored
+            .map(|json| serde_json::from_str(&json).map_err(|e| StateError::Backend(e.to_string())))
+            .transpose()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn failure(e: rusqlite::Error) -> StateError {
+    StateError::Backend(e.to_string())
+}
+
+// sqlite integers are signed, times past its range are kept at the end of it
+#[cfg(feature = "sqlite")]
+fn sql_millis(millis: u64) -> i64 {
+    i64::try_from(millis).unwrap_or(i64::MAX)
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStore {
+    fn get(&self, ns: &str, key: &str) -> Result<Option<PicoValue>, StateError> {
+        let now = self.clock.now_millis();
+        if purge_due(&self.last_purge, now) {
+            self.purge_expired()?;
+        }
+        let connection = self.connection.lock().unwrap();
+        Self::read(&connection, ns, key, sql_millis(now))
+    }
+
+    fn set(
+        &self,
+        ns: &str,
+        key: &str,
+        value: &PicoValue,
+        ttl: Option<Duration>,
+    ) -> Result<(), StateError> {
+        self.transaction(|tx, now| {
+            let expires = expires_at(now as u64, ttl).map(sql_millis);
+            tx.execute(
+                "INSERT OR REPLACE INTO pico_state (namespace, key, value, expires) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![ns, key, value.to_string(), expires],
+            )
+            .map_err(failure)?;
+            Ok(())
+        })
+    }
+
+    fn incr(&self, ns: &str, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64, StateError> {
+        self.transaction(|tx, now| match Self::read(tx, ns, key, now)? {
+            Some(value) => {
+                let total = add(ns, key, as_integer(ns, key, &value)?, by)?;
+                tx.execute(
+                    "UPDATE pico_state SET value = ?3 WHERE namespace = ?1 AND key = ?2",
+                    rusqlite::params![ns, key, total.to_string()],
+                )
+                .map_err(failure)?;
+                Ok(total)
+            }
+            None => {
+                // replaces an expired row that has not been purged yet
+                let expires = expires_at(now as u64, ttl).map(sql_millis);
+                tx.execute(
+                    "INSERT OR REPLACE INTO pico_state (namespace, key, value, expires) VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![ns, key, by.to_string(), expires],
+                )
+                .map_err(failure)?;
+                Ok(by)
+            }
+        })
+    }
+
+    fn expire(&self, ns: &str, key: &str, ttl: Duration) -> Result<bool, StateError> {
+        self.transaction(|tx, now| {
+            let expires = expires_at(now as u64, Some(ttl)).map(sql_millis);
+            let updated = tx
+                .execute(
+                    "UPDATE pico_state SET expires = ?3 WHERE namespace = ?1 AND key = ?2
+                        AND (expires IS NULL OR expires > ?4)",
+                    rusqlite::params![ns, key, expires, now],
+                )
+                .map_err(failure)?;
+            Ok(updated > 0)
+        })
+    }
+
+    fn delete(&self, ns: &str, key: &str) -> Result<bool, StateError> {
+        self.transaction(|tx, now| {
+            let deleted = tx
+                .execute(
+                    "DELETE FROM pico_state WHERE namespace = ?1 AND key = ?2
+                        AND (expires IS NULL OR expires > ?3)",
+                    rusqlite::params![ns, key, now],
+                )
+                .map_err(failure)?;
+            Ok(deleted > 0)
+        })
+    }
+
+    fn purge_expired(&self) -> Result<(), StateError> {
+        let now = sql_millis(self.clock.now_millis());
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "DELETE FROM pico_state WHERE expires IS NOT NULL AND expires <= ?1",
+                rusqlite::params![now],
+            )
+            .map_err(failure)?;
+        Ok(())
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_counts_and_expires() {
+        let store = MemoryStore::new();
+        assert_eq!(store.incr("logins", "bob", 1, None).unwrap(), 1);
+        assert_eq!(store.incr("logins", "bob", 2, None).unwrap(), 3);
+
+        store
+            .set("logins", "bob", &PicoValue::from("x"), None)
+            .unwrap();
+        assert!(store.incr("logins", "bob", 1, None).is_err());
+
+        assert!(store
+            .expire("logins", "bob", Duration::from_millis(0))
+            .unwrap());
+        assert_eq!(store.get("logins", "bob").unwrap(), None);
+        assert!(!store.delete("logins", "bob").unwrap());
+
+        store.incr("logins", "max", i64::MAX, None).unwrap();
+        assert!(matches!(
+            store.incr("logins", "max", 1, None),
+            Err(StateError::Overflow { .. })
+        ));
+        // a ttl past the end of time never expires
+        store
+            .set(
+                "logins",
+                "forever",
+                &PicoValue::from(1),
+                Some(Duration::MAX),
+            )
+            .unwrap();
+        assert!(store.get("logins", "forever").unwrap().is_some());
+    }
+
+    #[test]
+    fn expired_keys_are_purged_by_the_runtime_clock() {
+        use crate::runtime::{ManualClock, PicoRuntime};
+
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let ttl = Some(Duration::from_secs(10));
+        let runtime = PicoRuntime::new()
+            .set_state_store(MemoryStore::new())
+            .set_clock(clock.clone());
+        let state = runtime.state();
+        state
+            .set("logins", "bob", &PicoValue::from(1), ttl)
+            .unwrap();
+        clock.advance(Duration::from_secs(9));
+        assert!(state.get("logins", "bob").unwrap().is_some());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(state.get("logins", "bob").unwrap(), None);
+
+        let store = MemoryStore::new().with_clock(clock.clone());
+        store
+            .set("logins", "bob", &PicoValue::from(1), ttl)
+            .unwrap();
+        store
+            .set("logins", "carol", &PicoValue::from(1), ttl)
+            .unwrap();
+        store
+            .set("logins", "alice", &PicoValue::from(1), None)
+            .unwrap();
+        clock.advance(Duration::from_secs(11));
+        store.get("logins", "alice").unwrap();
+        // expired keys nobody asked for are still held until the purge interval has passed
+        assert_eq!(store.len(), 3);
+        clock.advance(Duration::from_millis(PURGE_INTERVAL));
+        store.get("logins", "alice").unwrap();
+        assert_eq!(store.len(), 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_counts_and_expires() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        assert_eq!(store.incr("logins", "bob", 1, None).unwrap(), 1);
+        assert_eq!(store.incr("logins", "bob", 2, None).unwrap(), 3);
+        assert_eq!(
+            store.get("logins", "bob").unwrap(),
+            Some(PicoValue::from(3))
+        );
+
+        assert!(store
+            .expire("logins", "bob", Duration::from_millis(0))
+            .unwrap());
+        assert_eq!(store.get("logins", "bob").unwrap(), None);
+        // an expired row that is still there counts again from 0
+        assert_eq!(store.incr("logins", "bob", 1, None).unwrap(), 1);
+        assert!(store
+            .set(
+                "logins",
+                "forever",
+                &PicoValue::from(1),
+                Some(Duration::MAX)
+            )
+            .is_ok());
+        assert!(store.get("logins", "forever").unwrap().is_some());
+    }
+}
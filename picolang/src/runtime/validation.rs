@@ -0,0 +1,440 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+use super::cache::{LookupCache, PicoRulesCache};
+use crate::commands::Command;
+use crate::conditions::Condition;
+use crate::rules::lookups::LookupType;
+use crate::rules::schemas::RuleSchemas;
+use crate::rules::sources::{read_source_document, RuleSource};
+use crate::rules::walk::{walk_rulefile, RuleVisitor};
+use crate::rules::{IncludeFile, RuleFile};
+use crate::values::ValueProducer;
+
+/// A problem with an uploaded rule file, `path` is a JSON pointer into `rule`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ValidationError {
+    /// the uploaded rule, or an include that was loaded for it
+    pub rule: String,
+    pub path: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} [{}] {}",
+            self.rule, self.path, self.code, self.message
+        )
+    }
+}
+
+/// what an uploaded rule file needs installed alongside it
+pub(super) struct Validated {
+    pub errors: Vec<ValidationError>,
+    /// includes that were not in the rule set and came from the rule source
+    pub includes: Vec<(String, RuleFile)>,
+    pub lookups: LookupCache,
+}
+
+/// everything in one rule file that refers to something outside of itself
+#[derive(Default)]
+struct References<'r> {
+    includes: Vec<(String, &'r IncludeFile)>,
+    tables: Vec<(String, &'r str)>,
+    namespaces: Vec<(String, &'r str)>,
+    break_targets: Vec<(String, uuid::Uuid)>,
+    blocks: HashSet<uuid::Uuid>,
+}
+
+impl<'r> References<'r> {
+    fn of(rulefile: &'r RuleFile) -> Self {
+        let mut references = Self::default();
+        walk_rulefile(rulefile, &mut references);
+        references
+    }
+}
+
+impl<'r> RuleVisitor<'r> for References<'r> {
+    fn visit_include(&mut self, path: &str, include: &'r IncludeFile) {
+        self.includes.push((path.to_string(), include));
+        if let Some(with_namespaces) = &include.with_namespaces {
+            for ns in &with_namespaces.0 {
+                self.namespaces
+                    .push((format!("{}/with_namespaces", path), ns));
+            }
+        }
+    }
+
+    fn visit_command(&mut self, path: &str, command: &'r Command) {
+        match command {
+            Command::Set(set) => {
+                for ns in set.namespaces.iter().flatten() {
+                    self.namespaces.push((format!("{}/namespaces", path), ns));
+                }
+            }
+            Command::BreakTo(break_to) => {
+                self.break_targets
+                    .push((format!("{}/break", path), break_to.r#break));
+            }
+            Command::IfThenElse(ite) => {
+                self.blocks.insert(ite.uuid);
+            }
+            Command::StateSet(state) => self.state_namespace(path, "state_set", &state.state_set.0),
+            Command::StateIncr(state) => {
+                self.state_namespace(path, "state_incr", &state.state_incr.0)
+            }
+            Command::StateExpire(state) => {
+                self.state_namespace(path, "state_expire", &state.state_expire.0)
+            }
+            Command::StateDelete(state) => {
+                self.state_namespace(path, "state_del", &state.state_del.0)
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_condition(&mut self, path: &str, condition: &'r Condition) {
+        if let Condition::InTable(in_table) = condition {
+            self.tables
+                .push((format!("{}/in_table/0", path), &in_table.in_table.0));
+        }
+    }
+
+    fn visit_value(&mut self, path: &str, value: &'r ValueProducer) {
+        match value {
+            ValueProducer::TableLookup(table_lookup) => self
+                .tables
+                .push((format!("{}/lookup/0", path), &table_lookup.lookup.0)),
+            ValueProducer::StateGet(state) => {
+                self.state_namespace(path, "state_get", &state.state_get.0)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'r> References<'r> {
+    fn state_namespace(&mut self, path: &str, command: &str, ns: &'r str) {
+        self.namespaces
+            .push((format!("{}/{}/0", path, command), ns));
+    }
+}
+
+/// the rule set as it would be once an upload is installed
+struct Candidate<'a> {
+    rulename: &'a str,
+    rulefile: &'a RuleFile,
+    includes: &'a [(String, RuleFile)],
+    rules: &'a PicoRulesCache,
+}
+
+impl<'a> Candidate<'a> {
+    fn get(&self, name: &str) -> Option<&'a RuleFile> {
+        if name == self.rulename {
+            return Some(self.rulefile);
+        }
+        match self.includes.iter().find(|(included, _)| included == name) {
+            Some((_, rulefile)) => Some(rulefile),
+            None => self
+                .rules
+                .get(name)
+                .and_then(|pico_rule| pico_rule.get_rulefile()),
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &'a RuleFile> + '_ {
+        self.rules
+            .values()
+            .filter(move |pico_rule| pico_rule.get_rulename() != self.rulename)
+            .filter_map(|pico_rule| pico_rule.get_rulefile())
+            .chain(std::iter::once(self.rulefile))
+            .chain(self.includes.iter().map(|(_, rulefile)| rulefile))
+    }
+
+    /// the first chain of includes from `name` that leads back to a rule already on it
+    fn include_cycle(&self, name: &str, chain: &mut Vec<String>) -> bool {
+        chain.push(name.to_string());
+        if chain[..chain.len() - 1].iter().any(|n| n == name) {
+            return true;
+        }
+        if let Some(rulefile) = self.get(name) {
+            for (_, include) in included_names(rulefile) {
+                if self.include_cycle(&include, chain) {
+                    return true;
+                }
+            }
+        }
+        chain.pop();
+        false
+    }
+}
+
+fn included_names(rulefile: &RuleFile) -> Vec<(String, String)> {
+    References::of(rulefile)
+        .includes
+        .into_iter()
+        .map(|(path, include)| (path, include.include.clone()))
+        .collect()
+}
+
+/// Checks `rulefile` could be installed as `rulename` in `rules`.
+///
+/// Includes not already in `rules` are read from `source`, as are external lookups
+/// missing from `lookup_cache`, they are returned rather than installed.
+/// An uploaded `rulefile` may only declare the namespaces in `allowed_namespaces`,
+/// `None` for a file from the rule source, which may declare any.
+pub(super) fn validate(
+    rulename: &str,
+    rulefile: &RuleFile,
+    rules: &PicoRulesCache,
+    lookup_cache: &LookupCache,
+    allowed_namespaces: Option<&HashSet<String>>,
+    source: &dyn RuleSource,
+) -> Validated {
+    let mut errors = Vec::new();
+    let error = |rule: &str, path: &str, code, message: String| ValidationError {
+        rule: rule.to_string(),
+        path: path.to_string(),
+        code,
+        message,
+    };
+
+    // resolve includes, following those of any include read from the source
+    let mut includes: Vec<(String, RuleFile)> = Vec::new();
+    let mut pending = vec![(rulename.to_string(), included_names(rulefile))];
+    while let Some((including, wanted)) = pending.pop() {
+        for (path, include) in wanted {
+            // files that failed to load are in the rule set without a rule file
+            let loaded = rules
+                .get(&include)
+                .and_then(|pico_rule| pico_rule.get_rulefile())
+                .is_some();
+            if include == rulename || loaded || includes.iter().any(|(name, _)| *name == include) {
+                continue;
+            }
+            match read_source_document::<RuleFile>(source, &include) {
+                Ok(included) => {
+                    info!("loaded {} for {}", include, including);
+                    pending.push((include.clone(), included_names(&included)));
+                    includes.push((include, included));
+                }
+                Err(e) => errors.push(error(
+                    &including,
+                    &path,
+                    "missing-include",
+                    format!("include {} not found: {}", include, e),
+                )),
+            }
+        }
+    }
+
+    let candidate = Candidate {
+        rulename,
+        rulefile,
+        includes: &includes,
+        rules,
+    };
+
+    let mut chain = Vec::new();
+    if candidate.include_cycle(rulename, &mut chain) {
+        errors.push(error(
+            rulename,
+            "/root",
+            "recursive-include",
+            format!("includes form a cycle: {}", chain.join(" -> ")),
+        ));
+    }
+
+    // declaring a namespace grants access to it, so an upload can not pick its own,
+    // not even one a published rule file already declares
+    if let Some(allowed) = allowed_namespaces {
+        for (i, ns) in rulefile.namespaces.iter().flatten().enumerate() {
+            if !allowed.contains(ns) {
+                errors.push(error(
+                    rulename,
+                    &format!("/namespaces/{}", i),
+                    "namespace-not-allowed",
+                    format!("namespace {} is not on the upload allow-list", ns),
+                ));
+            }
+        }
+    }
+
+    let declared: HashSet<&str> = candidate
+        .all()
+        .flat_map(|rf| rf.namespaces.iter().flatten())
+        .map(|ns| ns.as_str())
+        .collect();
+
+    let mut lookups = LookupCache::new();
+    let checked = std::iter::once((rulename, rulefile))
+        .chain(includes.iter().map(|(name, rf)| (name.as_str(), rf)));
+    for (name, rf) in checked {
+        if let Err((field, e)) = RuleSchemas::compile(rf) {
+            errors.push(error(name, &format!("/{}", field), "invalid-schema", e));
+        }
+
+        for (table, lookup) in &rf.lookups {
+            let path = format!("/lookups/{}", table);
+            let spec = match lookup {
+                LookupType::InternalTable(internal) => {
+                    if let Err(e) = internal.prepare() {
+                        errors.push(error(name, &path, "invalid-lookup", e));
+                    }
+                    continue;
+                }
+                external => match external.external_spec() {
+                    Some(spec) => spec,
+                    None => continue,
+                },
+            };
+            let cache_name = spec.cache_name();
+            if lookup_cache.has(&cache_name) || lookups.has(&cache_name) {
+                continue;
+            }
+            match spec.read(source) {
+                Ok(table) => match table.prepare() {
+                    Ok(()) => lookups.insert(&cache_name, table),
+                    Err(e) => errors.push(error(
+                        name,
+                        &path,
+                        "invalid-lookup",
+                        format!("lookup file {}: {}", spec.file, e),
+                    )),
+                },
+                Err(e) => errors.push(error(
+                    name,
+                    &path,
+                    "missing-lookup",
+                    format!("lookup file {} not loaded: {}", spec.file, e),
+                )),
+            }
+        }
+
+        let references = References::of(rf);
+        for (path, table) in &references.tables {
+            if !rf.lookups.contains_key(*table) {
+                errors.push(error(
+                    name,
+                    path,
+                    "unknown-table",
+                    format!("lookup table {} is not declared in lookups", table),
+                ));
+            }
+        }
+        for (path, ns) in &references.namespaces {
+            if !declared.contains(ns) {
+                errors.push(error(
+                    name,
+                    path,
+                    "undeclared-namespace",
+                    format!("namespace {} is not declared by any rule file", ns),
+                ));
+            }
+        }
+        for (path, target) in &references.break_targets {
+            if !references.blocks.contains(target) {
+                errors.push(error(
+                    name,
+                    path,
+                    "unknown-break-target",
+                    format!("no if/then/else with uuid {}", target),
+                ));
+            }
+        }
+    }
+
+    Validated {
+        errors,
+        includes,
+        lookups,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+    use crate::runtime::{test_run, test_runtime, PicoRuntime};
+    use serde_json::json;
+
+    #[test]
+    fn upload_is_validated_before_it_is_installed() {
+        let rt = test_runtime(&[
+            ("a.rule.json", r#"{"root": [{"set": ["answer", "first"]}]}"#),
+            ("child.rule.json", r#"{"root": [{"log": "child"}]}"#),
+            ("codes.json", r#"{"entries": {}, "default": 0}"#),
+        ]);
+        let first = rt.current_version().to_string();
+
+        let parse = |document: &str| StringLoader::new("upload.json", document).load().unwrap();
+
+        let rejected = rt.upload_rule(
+            "upload.rule.json",
+            parse(
+                r#"{"root": [
+                    {"include": "missing.rule.json"},
+                    {"set": ["x", {"lookup": ["nope", "k"]}], "namespaces": ["undeclared"]}
+                ]}"#,
+            ),
+        );
+        let codes: Vec<&str> = rejected
+            .as_ref()
+            .unwrap_err()
+            .iter()
+            .map(|e| e.code)
+            .collect();
+        assert_eq!(
+            codes,
+            vec!["missing-include", "unknown-table", "undeclared-namespace"]
+        );
+        assert_eq!(rejected.unwrap_err()[1].path, "/root/1/set/1/lookup/0");
+        assert_eq!(rt.current_version(), first);
+
+        let accepted = rt.upload_rule(
+            "upload.rule.json",
+            parse(
+                r#"{"lookups": {"codes": "codes.json"},
+                    "root": [{"include": "child.rule.json"}, {"set": ["x", {"lookup": ["codes", "k"]}]}]}"#,
+            ),
+        );
+        assert!(accepted.is_ok());
+        let result = test_run(&rt, "upload.rule.json", json!({})).unwrap();
+        assert_eq!(result["output"]["x"], json!(0));
+    }
+
+    #[test]
+    fn uploads_only_declare_allowed_namespaces() {
+        let rt = test_runtime(&[(
+            "logins.rule.json",
+            r#"{
+                "namespaces": ["logins"],
+                "root": [{"include": "upload.rule.json", "with_namespaces": "logins"}]
+            }"#,
+        )]);
+        let upload = |rt: &PicoRuntime, document: &str| {
+            let rulefile = StringLoader::new("upload.json", document).load().unwrap();
+            rt.upload_rule("upload.rule.json", rulefile)
+        };
+
+        // declared by a published rule file is not enough
+        let rejected =
+            upload(&rt, r#"{"namespaces": ["logins", "secrets"], "root": []}"#).unwrap_err();
+        let paths: Vec<&str> = rejected.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["/namespaces/0", "/namespaces/1"]);
+        assert!(rejected.iter().all(|e| e.code == "namespace-not-allowed"));
+
+        // an include grant from a published rule file is
+        upload(&rt, r#"{"root": [{"state_set": ["logins", "bob", 1]}]}"#).unwrap();
+        test_run(&rt, "upload.rule.json", json!({})).unwrap();
+        assert_eq!(rt.state().get("logins", "bob").unwrap(), None);
+        test_run(&rt, "logins.rule.json", json!({})).unwrap();
+        assert_eq!(rt.state().get("logins", "bob").unwrap(), Some(json!(1)));
+
+        let rt = rt.add_upload_namespaces(vec!["secrets".to_string()]);
+        assert!(upload(&rt, r#"{"namespaces": ["secrets"], "root": []}"#).is_ok());
+    }
+}
@@ -0,0 +1,351 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cache::{LookupCache, PicoRulesCache};
+use crate::errors::RuntimeError;
+
+/// how many published versions are kept for rollback
+const DEFAULT_MAX_VERSIONS: usize = 32;
+
+/// An immutable snapshot of every rule file and external lookup table in the runtime
+#[derive(Debug)]
+pub struct RuleSetVersion {
+    /// content hash of all the rule files and lookup tables in this version
+    pub id: String,
+    /// unix timestamp (seconds) of when the version was published
+    pub created: u64,
+    /// content hash of each rule file, keyed by rule name
+    pub rule_hashes: BTreeMap<String, String>,
+    /// content hash of each external lookup table, keyed by the name it is cached under
+    pub lookup_hashes: BTreeMap<String, String>,
+    pub rules: PicoRulesCache,
+    /// the tables the rules of this version look up, a rollback brings them back with the rules
+    pub lookups: Arc<LookupCache>,
+}
+
+impl RuleSetVersion {
+    pub fn new(rules: PicoRulesCache, lookups: Arc<LookupCache>) -> Self {
+        let rule_hashes: BTreeMap<String, String> = rules
+            .filenames()
+            .into_iter()
+            .map(|name| {
+                let hash = content_hash(
+                    rules
+                        .get(&name)
+                        .and_then(|pico_rule| pico_rule.get_rulefile()),
+                );
+                (name, hash)
+            })
+            .collect();
+        let lookup_hashes: BTreeMap<String, String> = lookups
+            .names()
+            .into_iter()
+            .map(|name| {
+                let hash = content_hash(lookups.get(&name));
+                (name, hash)
+            })
+            .collect();
+
+        // lookups are hashed apart from the rules, so a table can never collide with a rule file
+        let parts: Vec<&[u8]> = rule_hashes
+            .iter()
+            .flat_map(|(name, hash)| vec![name.as_bytes(), hash.as_bytes()])
+            .chain(std::iter::once(&b"lookups"[..]))
+            .chain(
+                lookup_hashes
+                    .iter()
+                    .flat_map(|(name, hash)| vec![name.as_bytes(), hash.as_bytes()]),
+            )
+            .collect();
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            id: hex_digest(&parts),
+            created,
+            rule_hashes,
+            lookup_hashes,
+            rules,
+            lookups,
+        }
+    }
+
+    pub fn info(&self, current: bool) -> RuleSetVersionInfo {
+        RuleSetVersionInfo {
+            id: self.id.clone(),
+            created: self.created,
+            current,
+            rules: self.rule_hashes.keys().cloned().collect(),
+        }
+    }
+}
+
+impl fmt::Display for RuleSetVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RuleSetVersion: {} created={} rules={}",
+            self.id,
+            self.created,
+            self.rule_hashes.len()
+        )
+    }
+}
+
+// through a `Value` so maps are hashed in key order rather than hash map order
+fn content_hash<T: Serialize>(content: Option<T>) -> String {
+    let document = content
+        .and_then(|content| serde_json::to_value(content).ok())
+        .and_then(|value| serde_json::to_vec(&value).ok())
+        .unwrap_or_default();
+    hex_digest(&[document.as_slice()])
+}
+
+fn hex_digest(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+        // separator so ["ab", "c"] and ["a", "bc"] hash differently
+        hasher.update([0u8]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Summary of a version, as listed by [`PicoRuntime::versions`](crate::runtime::PicoRuntime::versions)
+#[derive(Debug, Serialize)]
+pub struct RuleSetVersionInfo {
+    pub id: String,
+    pub created: u64,
+    pub current: bool,
+    pub rules: Vec<String>,
+}
+
+/// Rule files and lookup tables that differ between two versions
+#[derive(Debug, Serialize)]
+pub struct RuleSetDiff {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl RuleSetDiff {
+    pub fn between(from: &RuleSetVersion, to: &RuleSetVersion) -> Self {
+        let mut diff = Self {
+            from: from.id.clone(),
+            to: to.id.clone(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        diff.compare(&from.rule_hashes, &to.rule_hashes);
+        diff.compare(&from.lookup_hashes, &to.lookup_hashes);
+        diff
+    }
+
+    fn compare(&mut self, from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) {
+        self.added
+            .extend(to.keys().filter(|name| !from.contains_key(*name)).cloned());
+        self.removed
+            .extend(from.keys().filter(|name| !to.contains_key(*name)).cloned());
+        self.changed
+            .extend(from.iter().filter_map(|(name, hash)| match to.get(name) {
+                Some(other) if other != hash => Some(name.clone()),
+                _ => None,
+            }));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Published versions, oldest first, and the one requests currently run against
+#[derive(Debug)]
+pub struct RuleSetHistory {
+    current: Arc<RuleSetVersion>,
+    versions: Vec<Arc<RuleSetVersion>>,
+    max_versions: usize,
+}
+
+impl Default for RuleSetHistory {
+    fn default() -> Self {
+        Self {
+            current: Arc::new(RuleSetVersion::new(
+                PicoRulesCache::new(),
+                Arc::new(LookupCache::new()),
+            )),
+            versions: Vec::new(),
+            max_versions: DEFAULT_MAX_VERSIONS,
+        }
+    }
+}
+
+impl RuleSetHistory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set_max_versions(&mut self, max_versions: usize) {
+        self.max_versions = max_versions.max(1);
+    }
+
+    pub fn current(&self) -> &Arc<RuleSetVersion> {
+        &self.current
+    }
+
+    /// makes `rules` and `lookups` the current version in a single switch, returns its id
+    pub fn publish(&mut self, rules: PicoRulesCache, lookups: Arc<LookupCache>) -> String {
+        let version = Arc::new(RuleSetVersion::new(rules, lookups));
+        if version.id == self.current.id {
+            info!("rule set unchanged, still at {}", version.id);
+            return version.id.clone();
+        }
+
+        info!("publishing {}", version);
+        // republishing older content makes that version the newest rather than duplicating it
+        self.versions.retain(|v| v.id != version.id);
+        self.versions.push(version.clone());
+        if self.versions.len() > self.max_versions {
+            let dropped = self.versions.remove(0);
+            debug!("dropped oldest version {}", dropped.id);
+        }
+        self.current = version;
+        self.current.id.clone()
+    }
+
+    /// finds a version by its id, or an unambiguous prefix of it
+    pub fn find(&self, version: &str) -> Result<&Arc<RuleSetVersion>, RuntimeError> {
+        let mut matching = self
+            .versions
+            .iter()
+            .filter(|v| !version.is_empty() && v.id.starts_with(version));
+
+        match (matching.next(), matching.next()) {
+            (Some(found), None) => Ok(found),
+            _ => Err(RuntimeError::NoSuchVersion {
+                version: version.to_string(),
+            }),
+        }
+    }
+
+    pub fn rollback(&mut self, version: &str) -> Result<String, RuntimeError> {
+        let target = self.find(version)?.clone();
+        warn!("rolling back from {} to {}", self.current.id, target.id);
+        self.current = target;
+        Ok(self.current.id.clone())
+    }
+
+    pub fn list(&self) -> Vec<RuleSetVersionInfo> {
+        self.versions
+            .iter()
+            .map(|v| v.info(Arc::ptr_eq(v, &self.current)))
+            .collect()
+    }
+
+    pub fn diff(&self, from: &str, to: &str) -> Result<RuleSetDiff, RuntimeError> {
+        Ok(RuleSetDiff::between(self.find(from)?, self.find(to)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::RuleFileError;
+    use crate::rules::loaders::{PicoRuleLoader, StringLoader};
+    use crate::rules::sources::{MemorySource, RuleSource};
+    use crate::runtime::{test_run, test_runtime, PicoRuntime};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    // a source the test can change between reloads
+    #[derive(Debug, Clone, Default)]
+    struct SharedSource(Arc<Mutex<MemorySource>>);
+
+    impl RuleSource for SharedSource {
+        fn names(&self) -> Result<Vec<String>, RuleFileError> {
+            self.0.lock().unwrap().names()
+        }
+
+        fn read(&self, name: &str) -> Result<String, RuleFileError> {
+            self.0.lock().unwrap().read(name)
+        }
+    }
+
+    #[test]
+    fn lookup_tables_belong_to_their_version() {
+        let source = SharedSource::default();
+        let edit = |csv: &str| source.0.lock().unwrap().insert("codes.lookup.csv", csv);
+        edit("code,name\nA,first\n");
+        source.0.lock().unwrap().insert(
+            "codes.rule.json",
+            r#"{"lookups": {"codes": "codes.lookup.csv"},
+                "root": [{"set": ["name", {"lookup": ["codes", "A"], "column": "name"}]}]}"#,
+        );
+        let rt = PicoRuntime::new()
+            .set_rule_source(source.clone())
+            .initialise();
+        let first = rt.current_version();
+
+        let name = |version: Option<&str>| {
+            let mut ctx = rt.make_ctx(json!({}));
+            let result = match version {
+                Some(v) => rt.exec_rule_at_version(v, "codes.rule.json", &mut ctx),
+                None => rt.exec_rule_with_context("codes.rule.json", &mut ctx),
+            };
+            result.unwrap()["output"]["name"].clone()
+        };
+
+        // only the table changes, that is still a new version
+        edit("code,name\nA,second\n");
+        let second = rt.reload().unwrap();
+        assert_ne!(first, second);
+        let diff = rt.diff_versions(&first, &second).unwrap();
+        assert_eq!(diff.changed, vec!["codes.lookup.csv".to_string()]);
+        assert_eq!(name(None), json!("second"));
+        assert_eq!(name(Some(&first)), json!("first"));
+
+        rt.rollback(&first).unwrap();
+        assert_eq!(name(None), json!("first"));
+    }
+
+    #[test]
+    fn publish_diff_and_rollback() {
+        let rt = test_runtime(&[("a.rule.json", r#"{"root": [{"set": ["answer", "first"]}]}"#)]);
+        let first = rt.current_version().to_string();
+
+        let second_rule = StringLoader::new(
+            "b.rule.json",
+            r#"{"root": [{"set": ["answer", "second"]}]}"#,
+        )
+        .load()
+        .unwrap();
+        let second = rt.post_rule("a.rule.json", second_rule);
+        assert_ne!(first, second);
+
+        let diff = rt.diff_versions(&first, &second).unwrap();
+        assert_eq!(diff.changed, vec!["a.rule.json".to_string()]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+        let answer = |version: Option<&str>| {
+            let result = match version {
+                Some(v) => rt.exec_rule_at_version(v, "a.rule.json", &mut rt.make_ctx(json!({}))),
+                None => test_run(&rt, "a.rule.json", json!({})),
+            };
+            result.unwrap()["output"]["answer"].clone()
+        };
+        assert_eq!(answer(None), json!("second"));
+        assert_eq!(answer(Some(&first[..12])), json!("first"));
+
+        rt.rollback(&first).unwrap();
+        assert_eq!(rt.current_version(), first);
+        assert_eq!(answer(None), json!("first"));
+    }
+}
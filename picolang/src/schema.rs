@@ -0,0 +1,54 @@
+//! JSON Schema for rule and lookup files, generated from the types they deserialize into
+//! so editors validate against exactly what the runtime accepts.
+
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
+
+use crate::rules::lookups::LookupTable;
+use crate::rules::RuleFile;
+
+fn schema_for<T: JsonSchema>(title: &str) -> RootSchema {
+    let mut schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<T>();
+    schema.schema.metadata().title = Some(title.to_string());
+    schema
+}
+
+/// schema of a `.rule.json` file
+pub fn rule_file_schema() -> RootSchema {
+    schema_for::<RuleFile>("Pico-Lang rule")
+}
+
+/// schema of a `.lookup.json` file
+pub fn lookup_table_schema() -> RootSchema {
+    schema_for::<LookupTable>("Pico-Lang lookup table")
+}
+
+/// pretty printed, as checked in under `schema/`
+pub fn to_json(schema: &RootSchema) -> String {
+    serde_json::to_string_pretty(schema).unwrap_or_default() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_in_schemas_are_current() {
+        let regenerate = "out of date, regenerate with `cargo run --bin pico -- schema`";
+        assert_eq!(
+            include_str!("../../schema/schema.json"),
+            to_json(&rule_file_schema()),
+            "schema/schema.json {}",
+            regenerate
+        );
+        assert_eq!(
+            include_str!("../../schema/lookup.schema.json"),
+            to_json(&lookup_table_schema()),
+            "schema/lookup.schema.json {} --lookup",
+            regenerate
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Test suites for rule files, `.test.json` files kept next to the rules they test.
+//!
+//! ```json
+//! {
+//!   "rule": "scoring.rule.json",
+//!   "cases": [
+//!     {
+//!       "name": "british customers are low risk",
+//!       "input": {"country": "GB"},
+//!       "globals": {"region": "eu"},
+//!       "lookups": {"risk": {"entries": {"GB": "low"}, "default": "high"}},
+//!       "output": {"score": "low"},
+//!       "stop": "scored"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Only the variables given in `output` and `namespaced` are compared,
+//! objects are compared key by key.
+
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::errors::RuleFileError;
+use crate::rules::loaders::read_document;
+use crate::rules::lookups::LookupTable;
+use crate::runtime::PicoRuntime;
+use crate::values::PicoValue;
+
+pub const TEST_FILE_SUFFIX: &str = ".test.json";
+
+pub fn is_test_file(filename: &str) -> bool {
+    filename.ends_with(TEST_FILE_SUFFIX)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TestSuite {
+    /// the rule file every case runs
+    pub rule: String,
+    pub cases: Vec<TestCase>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    #[serde(default = "TestCase::empty_input")]
+    pub input: PicoValue,
+    /// globals the rules read in place of the runtime's
+    #[serde(default)]
+    pub globals: HashMap<String, PicoValue>,
+    /// tables used in place of those the rule files declare under the same name
+    #[serde(default)]
+    pub lookups: HashMap<String, Arc<LookupTable>>,
+
+    /// expected local variables
+    pub output: Option<PicoValue>,
+    /// expected namespaced variables
+    pub namespaced: Option<PicoValue>,
+    /// the reason the rules are expected to stop with
+    pub stop: Option<String>,
+}
+
+impl TestCase {
+    fn empty_input() -> PicoValue {
+        json!({})
+    }
+}
+
+/// A value that is not what the case expected, `path` is a JSON pointer into the final context
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    pub expected: PicoValue,
+    /// None when the rules did not set it
+    pub actual: Option<PicoValue>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(
+                f,
+                "{}: expected {}, got {}",
+                self.path, self.expected, actual
+            ),
+            None => write!(f, "{}: expected {}, got nothing", self.path, self.expected),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CaseReport {
+    pub name: String,
+    pub seconds: f64,
+    pub differences: Vec<Difference>,
+    /// the rule could not be run at all
+    pub error: Option<String>,
+}
+
+impl CaseReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.differences.is_empty()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SuiteReport {
+    /// usually the test file name
+    pub name: String,
+    pub rule: String,
+    pub cases: Vec<CaseReport>,
+}
+
+impl SuiteReport {
+    pub fn failures(&self) -> usize {
+        self.cases.iter().filter(|case| !case.passed()).count()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failures() == 0
+    }
+}
+
+pub fn read_suite(filename: &str) -> Result<TestSuite, RuleFileError> {
+    read_document(filename)
+}
+
+/// runs every case of `suite` against the current rules of `runtime`
+pub fn run_suite(runtime: &PicoRuntime, name: &str, suite: &TestSuite) -> SuiteReport {
+    SuiteReport {
+        name: name.to_string(),
+        rule: suite.rule.clone(),
+        cases: suite
+            .cases
+            .iter()
+            .map(|case| run_case(runtime, &suite.rule, case))
+            .collect(),
+    }
+}
+
+fn run_case(runtime: &PicoRuntime, rulename: &str, case: &TestCase) -> CaseReport {
+    let started = Instant::now();
+    let mut ctx = runtime.make_ctx(case.input.clone());
+    for (key, value) in &case.globals {
+        ctx.override_global(key, value.clone());
+    }
+    for (table_name, table) in &case.lookups {
+        ctx.override_table(table_name, table.clone());
+    }
+
+    let mut differences = Vec::new();
+    let error = match runtime.exec_rule_with_context(rulename, &mut ctx) {
+        Ok(final_ctx) => {
+            let expected = [
+                ("output", case.output.clone()),
+                ("namespaced", case.namespaced.clone()),
+                ("stop", case.stop.clone().map(PicoValue::String)),
+            ];
+            for (key, expected) in expected {
+                if let Some(expected) = expected {
+                    compare(
+                        &format!("/{}", key),
+                        &expected,
+                        final_ctx.get(key),
+                        &mut differences,
+                    );
+                }
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+
+    CaseReport {
+        name: case.name.clone(),
+        seconds: started.elapsed().as_secs_f64(),
+        differences,
+        error,
+    }
+}
+
+// objects only need the keys that are expected, anything else must be equal
+fn compare(
+    path: &str,
+    expected: &PicoValue,
+    actual: Option<&PicoValue>,
+    differences: &mut Vec<Difference>,
+) {
+    match (expected, actual) {
+        (PicoValue::Object(expected), Some(PicoValue::Object(actual))) => {
+            for (key, value) in expected {
+                let key_path = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+                compare(&key_path, value, actual.get(key), differences);
+            }
+        }
+        (expected, Some(actual)) if expected == actual => {}
+        (expected, actual) => differences.push(Difference {
+            path: path.to_string(),
+            expected: expected.clone(),
+            actual: actual.cloned(),
+        }),
+    }
+}
+
+/// the reports as JUnit XML, one `testsuite` per suite, for CI servers
+pub fn junit_xml(reports: &[SuiteReport]) -> String {
+    let tests: usize = reports.iter().map(|report| report.cases.len()).sum();
+    let failures: usize = reports.iter().map(|report| report.failures()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\">\n",
+        tests, failures
+    ));
+    for report in reports {
+        let seconds: f64 = report.cases.iter().map(|case| case.seconds).sum();
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+            escape_xml(&report.name),
+            report.cases.len(),
+            report.failures(),
+            seconds
+        ));
+        for case in &report.cases {
+            xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                escape_xml(&case.name),
+                escape_xml(&report.rule),
+                case.seconds
+            ));
+            if let Some(error) = &case.error {
+                xml.push_str(&format!(
+                    ">\n      <error message=\"{}\"/>\n    </testcase>\n",
+                    escape_xml(error)
+                ));
+            } else if !case.differences.is_empty() {
+                let details: Vec<String> = case.differences.iter().map(|d| d.to_string()).collect();
+                xml.push_str(&format!(
+                    ">\n      <failure message=\"{} differences\">{}</failure>\n    </testcase>\n",
+                    case.differences.len(),
+                    escape_xml(&details.join("\n"))
+                ));
+            } else {
+                xml.push_str("/>\n");
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::sources::MemorySource;
+
+    #[test]
+    fn cases_report_differences() {
+        let source = MemorySource::new()
+            .with_document(
+                "score.rule.json",
+                r#"{
+                    "lookups": {"risk": "risk.lookup.json"},
+                    "root": [
+                        {"set": ["score", {"lookup": ["risk", {"var": "country"}]}]},
+                        {"set": ["region", {"global": "region", "default": "none"}]},
+                        {"if": {"eq": [{"var": "score"}, "high"]}, "then": {"stop": "refer"}},
+                        {"set": ["approved", true]}
+                    ]
+                }"#,
+            )
+            .with_document(
+                "risk.lookup.json",
+                r#"{"entries": {"GB": "low"}, "default": "high"}"#,
+            );
+        let rt = PicoRuntime::new().set_rule_source(source).initialise();
+
+        let suite: TestSuite = serde_json::from_value(json!({
+            "rule": "score.rule.json",
+            "cases": [
+                {"name": "low", "input": {"country": "GB"}, "output": {"score": "low", "approved": true}},
+                {"name": "referred", "input": {"country": "FR"}, "stop": "refer", "output": {"score": "high"}},
+                {"name": "overridden", "input": {"country": "FR"}, "globals": {"region": "eu"},
+                 "lookups": {"risk": {"entries": {"FR": "low"}}},
+                 "output": {"score": "low", "region": "eu"}},
+                {"name": "wrong", "input": {"country": "FR"}, "output": {"approved": true}}
+            ]
+        }))
+        .unwrap();
+        let report = run_suite(&rt, "score.test.json", &suite);
+
+        let passed: Vec<bool> = report.cases.iter().map(|case| case.passed()).collect();
+        assert_eq!(passed, vec![true, true, true, false]);
+        assert_eq!(
+            report.cases[3].differences,
+            vec![Difference {
+                path: "/output/approved".to_string(),
+                expected: json!(true),
+                actual: None,
+            }]
+        );
+
+        let xml = junit_xml(&[report]);
+        assert!(xml.contains("<testsuites tests=\"4\" failures=\"1\">"));
+        assert!(xml.contains("/output/approved: expected true, got nothing"));
+    }
+}
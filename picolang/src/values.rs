@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::Value;
@@ -7,9 +8,11 @@ use serde_json::Value;
 pub type PicoValue = Value;
 
 use crate::commands::execution::{ValueExecution, ValueResult};
+use crate::commands::state::state_key;
 use crate::context::PicoContext;
 use crate::errors::PicoError;
-use crate::rules::{lookups::LookupType, PicoRules};
+use crate::rules::lookups::{key_part, COMPOSITE_KEY_SEPARATOR};
+use crate::rules::PicoRules;
 use crate::runtime::PicoRuntime;
 use regex::Regex;
 use std::collections::HashMap;
@@ -28,7 +31,7 @@ impl ValueExecution for PicoValue {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum VarValue {
     /// VarName to lookup
@@ -39,11 +42,11 @@ pub enum VarValue {
 
 ///
 /// Getting a PicoValue
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct VarLookup {
     /// String
     /// String, [`PicoValue`](crate::values::PicoValue)
-    var: VarValue,
+    pub(crate) var: VarValue,
 }
 
 impl ValueExecution for VarLookup {
@@ -78,7 +81,7 @@ impl ValueExecution for VarLookup {
 }
 
 /// Types of Var's that can be used
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum Var {
     /// A literal String
@@ -105,7 +108,7 @@ impl ValueExecution for Var {
 }
 
 /// All things that can produce a PicoValue
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum ValueProducer {
     /// a JSON pointer
@@ -113,7 +116,7 @@ pub enum ValueProducer {
     /// A Var
     VarLookup(VarLookup),
     /// Lookup in a table in a PicoRule file
-    TableLookup(TableLookup),
+    TableLookup(Box<TableLookup>),
 
     /// Slice of a String
     Slice(Slice),
@@ -122,6 +125,11 @@ pub enum ValueProducer {
     ConCat(ConCat),
     Extract(Box<Extract>),
 
+    /// a value from the runtime's state store
+    StateGet(StateGet),
+    /// a runtime global
+    Global(Global),
+
     LiteralString(LiteralString),
     LiteralI64(LiteralI64),
 
@@ -149,16 +157,23 @@ impl ValueExecution for ValueProducer {
                 literal.run_with_context(pico_rules, runtime, ctx)
             }
             ValueProducer::TableLookup(lookup) => lookup.run_with_context(pico_rules, runtime, ctx),
+            ValueProducer::StateGet(state) => state.run_with_context(pico_rules, runtime, ctx),
+            ValueProducer::Global(global) => global.run_with_context(pico_rules, runtime, ctx),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ExtractInternal(#[serde(with = "serde_regex")] Regex, ValueProducer);
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ExtractInternal(
+    #[serde(with = "serde_regex")]
+    #[schemars(with = "String")]
+    pub(crate) Regex,
+    pub(crate) ValueProducer,
+);
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Extract {
-    extract: ExtractInternal,
+    pub(crate) extract: ExtractInternal,
 }
 
 /*
@@ -204,10 +219,10 @@ impl ValueExecution for Extract {
 }
 
 /// ConCat from a JSON array of other ValueProducers that produce Strings
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct ConCat {
     /// Array of [`ValueProducer`](crate::values::ValueProducer)
-    concat: Vec<ValueProducer>,
+    pub(crate) concat: Vec<ValueProducer>,
 }
 
 impl ValueExecution for ConCat {
@@ -244,9 +259,9 @@ impl ValueExecution for ConCat {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Slice {
-    slice: (Box<ValueProducer>, isize, Option<isize>),
+    pub(crate) slice: (Box<ValueProducer>, isize, Option<isize>),
 }
 
 fn slice_starts_at(requested_start: isize, vec_length: usize) -> usize {
@@ -343,7 +358,7 @@ impl ValueExecution for Slice {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(untagged)]
 pub enum PointerValue {
     /// JSON Pointer path applied to the input JSON
@@ -353,9 +368,9 @@ pub enum PointerValue {
     VarPointer(String, VarLookup),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Pointer {
-    pointer: PointerValue, // JSON pointer
+    pub(crate) pointer: PointerValue, // JSON pointer
 }
 impl fmt::Display for PointerValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -400,8 +415,8 @@ impl ValueExecution for Pointer {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct LiteralString(String);
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct LiteralString(pub(crate) String);
 
 impl ValueExecution for LiteralString {
     fn run_with_context(
@@ -416,7 +431,7 @@ impl ValueExecution for LiteralString {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct LiteralI64(i64);
 
 impl ValueExecution for LiteralI64 {
@@ -432,10 +447,47 @@ impl ValueExecution for LiteralI64 {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The key of a table lookup, a list of values makes a composite key
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(untagged)]
+pub enum LookupKey {
+    Composite(Vec<ValueProducer>),
+    Single(Box<ValueProducer>),
+}
+
+impl ValueExecution for LookupKey {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ValueResult {
+        match self {
+            LookupKey::Single(producer) => Ok(PicoValue::String(key_part(
+                &producer.run_with_context(pico_rules, runtime, ctx)?,
+            ))),
+            LookupKey::Composite(producers) => {
+                let mut parts = Vec::with_capacity(producers.len());
+                for producer in producers {
+                    parts.push(key_part(
+                        &producer.run_with_context(pico_rules, runtime, ctx)?,
+                    ));
+                }
+                Ok(PicoValue::String(parts.join(COMPOSITE_KEY_SEPARATOR)))
+            }
+        }
+    }
+}
+
+/// `{"lookup": [table, key], "column": name, "default": value}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct TableLookup {
-    /// table name
-    lookup: (String, String), // table, key
+    /// table name and key
+    pub(crate) lookup: (String, LookupKey),
+    /// field to take from an entry that is an object
+    pub(crate) column: Option<String>,
+    /// value when the key is not in the table, instead of the table's default
+    pub(crate) default: Option<PicoValue>,
 }
 
 // lookup can use a table in this file (InternalTable)
@@ -445,32 +497,121 @@ impl ValueExecution for TableLookup {
         &self,
         pico_rules: &PicoRules,
         runtime: &PicoRuntime,
-        _ctx: &mut PicoContext,
+        ctx: &mut PicoContext,
     ) -> ValueResult {
-        info!(
-            "Lookup Dictionary {:?} -> {:?}",
-            self.lookup.0, self.lookup.1
-        );
-
-        let value: Option<&PicoValue> = match pico_rules.get_table(&self.lookup.0) {
-            None => None,
-            Some(table_type) => match table_type {
-                LookupType::InternalTable(internal_table) => {
-                    Some(internal_table.lookup(&self.lookup.1))
+        let (table_name, key) = &self.lookup;
+        let key = key_part(&key.run_with_context(pico_rules, runtime, ctx)?);
+        info!("Lookup Dictionary {:?} -> {:?}", table_name, key);
+
+        let table = pico_rules.resolve_table(runtime, ctx, table_name)?;
+
+        let value = match (table.get(&key), &self.default) {
+            (Some(value), _) => value,
+            // a default given with the lookup expects misses
+            (None, Some(default)) => default,
+            (None, None) => {
+                if table.strict {
+                    return Err(PicoError::NoSuchValue(format!(
+                        "No key {} in table {}",
+                        key, table_name
+                    )));
                 }
-
-                LookupType::ExternalTable(external_table) => {
-                    runtime.table_lookup(external_table, &self.lookup.1)
-                }
-            },
+                ctx.warn(format!("lookup of {} missed table {}", key, table_name));
+                &table.default
+            }
         };
 
-        match value {
-            Some(v) => Ok(v.clone()),
-            None => Err(PicoError::NoSuchValue(format!(
-                "No Such table {}",
-                &self.lookup.0
-            ))),
+        match &self.column {
+            None => Ok(value.clone()),
+            Some(column) => value.get(column).cloned().ok_or_else(|| {
+                PicoError::NoSuchValue(format!("No column {} in table {}", column, table_name))
+            }),
         }
     }
 }
+
+/// `{"state_get": [namespace, key], "default": value}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct StateGet {
+    pub(crate) state_get: (String, Box<ValueProducer>),
+    /// returned when the key is not set or has expired
+    pub(crate) default: Option<PicoValue>,
+}
+
+impl ValueExecution for StateGet {
+    fn run_with_context(
+        &self,
+        pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ValueResult {
+        let (ns, key) = &self.state_get;
+        let key = state_key(ns, key, pico_rules, runtime, ctx)?;
+
+        match (runtime.state().get(ns, &key)?, &self.default) {
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => Err(PicoError::NoSuchValue(format!("{}::{}", ns, key))),
+        }
+    }
+}
+
+/// `{"global": name, "default": value}`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Global {
+    pub(crate) global: String,
+    /// returned when the global is not set
+    pub(crate) default: Option<PicoValue>,
+}
+
+impl ValueExecution for Global {
+    fn run_with_context(
+        &self,
+        _pico_rules: &PicoRules,
+        runtime: &PicoRuntime,
+        ctx: &mut PicoContext,
+    ) -> ValueResult {
+        if let Some(value) = ctx.global_override(&self.global) {
+            return Ok(value.clone());
+        }
+        match (runtime.global_get(&self.global), &self.default) {
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => Err(PicoError::NoSuchValue(format!("global {}", self.global))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{test_run, test_runtime};
+    use serde_json::json;
+
+    #[test]
+    fn lookup_keys_come_from_the_input() {
+        let rt = test_runtime(&[
+            (
+                "rates.lookup.csv",
+                "country,product,rate,currency\nGB,loan,4.5,GBP\nFR,loan,3.9,EUR\n",
+            ),
+            (
+                "pricing.rule.json",
+                r#"{
+                    "lookups": {
+                        "rates": {"file": "rates.lookup.csv", "key": ["country", "product"]},
+                        "names": {"entries": {"GB": "United Kingdom"}, "default": "unknown"}
+                    },
+                    "root": [
+                        {"set": ["rate", {"lookup": ["rates", [{"var": "country"}, {"var": "product"}]], "column": "rate"}]},
+                        {"set": ["name", {"lookup": ["names", {"var": "country"}]}]}
+                    ]
+                }"#,
+            ),
+        ]);
+
+        let input = json!({"country": "GB", "product": "loan"});
+        let result = test_run(&rt, "pricing.rule.json", input).unwrap();
+        assert_eq!(result["output"]["rate"], json!(4.5));
+        assert_eq!(result["output"]["name"], json!("United Kingdom"));
+    }
+}